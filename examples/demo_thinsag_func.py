@@ -2,6 +2,8 @@ import os, json, requests, openai
 
 OPENAI_API_KEY = os.environ["OPENAI_API_KEY"]
 SAG_HOST        = "http://127.0.0.1:8900"
+SAG_TOKEN       = open(os.path.expanduser("~/.thin-sag/.sagtoken")).read().strip()
+SAG_HEADERS     = {"X-SAG-TOKEN": SAG_TOKEN}
 
 openai.api_key = OPENAI_API_KEY
 prompt = """You are an assistant that controls my Mac via Thin‑SAG.
@@ -10,35 +12,10 @@ Notes' bundle name is com.apple.Notes.
 
 Use {secret.profile_name}.
 """
-functions = [
-    {
-        "name": "run_actions",
-        "description": "Send a sequence of UI actions to Thin‑SAG",
-        "parameters": {
-            "type": "object",
-            "properties": {
-                "actions": {
-                    "type": "array",
-                    "items": {
-                        "type": "object",
-                        "required": ["act"],
-                        "properties": {
-                            "act":   {"type": "string", "enum": ["launch", "type", "click", "wait"]},
-                            "target": {"type": "string"},
-                            "text":   {"type": "string"},
-                            "x":      {"type": "integer"},
-                            "y":      {"type": "integer"},
-                            "ms":     {"type": "integer"}
-                        }
-                    }
-                }
-            },
-            "required": ["actions"]
-        }
-    }
-]
-
-
+# Tool definitions are generated by Thin‑SAG from its action enum and policy
+# (GET /schema/actions), so they always match what /run-json accepts.
+schema = requests.get(f"{SAG_HOST}/schema/actions", headers=SAG_HEADERS).json()
+functions = [tool["function"] for tool in schema["openai"]]
 
 resp = openai.ChatCompletion.create(
     model="gpt-4o-mini",
@@ -52,10 +29,10 @@ print("LLM output actions ➜", json.dumps(actions, indent=2))
 
 
 r = requests.post(f"{SAG_HOST}/run-json",
-                  headers={"Content-Type": "application/json"},
-                  data=json.dumps(actions["actions"]))  # 修正: actions["actions"] を送信
+                  headers={**SAG_HEADERS, "Content-Type": "application/json"},
+                  data=json.dumps(actions))  # { "actions": [...], "defaults": {...} }
 
-if r.status_code != 200:
+if r.status_code != 202:
     print(f"Error: Received status code {r.status_code}")
     print("Response text:", r.text)
     exit(1)
@@ -63,10 +40,18 @@ if r.status_code != 200:
 job_id = r.json()["job_id"]
 print("Job ID =", job_id)
 
-# ③ ポーリングして結果取得
-while True:
-    status = requests.get(f"{SAG_HOST}/job/{job_id}").json()
-    if status["status"] != "Running":
-        break
+# ③ 進捗をイベントで受け取る (Server-Sent Events, no polling)
+status = None
+with requests.get(f"{SAG_HOST}/job/{job_id}/events", headers=SAG_HEADERS,
+                  stream=True) as events:
+    for line in events.iter_lines(decode_unicode=True):
+        if not line or not line.startswith("data:"):
+            continue
+        event = json.loads(line[len("data:"):])
+        if event["event"] in ("step_started", "step_finished"):
+            print(event["event"], event["step"], event["act"], event["status"])
+        elif event["event"] == "finished":
+            status = event
+            break
 
 print("Final Status =", status)
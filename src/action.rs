@@ -1,32 +1,414 @@
-use serde::Deserialize;
-#[derive(Deserialize, Debug)]
-pub struct ActionList(pub Vec<Action>);
+use crate::keymap::{Layout, TypeMode};
+use crate::tree::WindowSelector;
+use schemars::JsonSchema;
+use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
+use serde::de::{MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
 
-#[derive(Deserialize, Debug)]
+/// A job: either a bare array of steps or
+/// `{ "defaults": { "retries": 2 }, "actions": [...] }`.
+#[derive(Debug, Default, JsonSchema)]
+pub struct ActionList {
+    /// options for steps that do not set their own
+    #[serde(default)]
+    pub defaults: StepOptions,
+    pub actions: Vec<Step>,
+}
+
+#[derive(Deserialize)]
+struct ActionDoc {
+    #[serde(default)]
+    defaults: StepOptions,
+    actions: Vec<Step>,
+}
+
+impl<'de> Deserialize<'de> for ActionList {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        struct ListVisitor;
+
+        impl<'de> Visitor<'de> for ListVisitor {
+            type Value = ActionList;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an array of actions or { defaults, actions }")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<ActionList, A::Error> {
+                Ok(ActionList {
+                    defaults: StepOptions::default(),
+                    actions: Deserialize::deserialize(SeqAccessDeserializer::new(seq))?,
+                })
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<ActionList, A::Error> {
+                let doc = ActionDoc::deserialize(MapAccessDeserializer::new(map))?;
+                Ok(ActionList {
+                    defaults: doc.defaults,
+                    actions: doc.actions,
+                })
+            }
+        }
+
+        de.deserialize_any(ListVisitor)
+    }
+}
+
+/// One entry of an action list: the action plus how to run it.
+#[derive(Deserialize, Debug, JsonSchema)]
+pub struct Step {
+    #[serde(flatten)]
+    pub meta: StepMeta,
+    #[serde(flatten)]
+    pub act: Action,
+    #[serde(flatten)]
+    pub options: StepOptions,
+}
+
+/// Caller-supplied names for a step, echoed in job progress, errors and
+/// the audit log.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, JsonSchema)]
+pub struct StepMeta {
+    /// short unique name such as `fill-email`, shown instead of the step number
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// what the step does
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// why the step is needed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rationale: Option<String>,
+}
+
+impl StepMeta {
+    /// How messages name the step: its `id` (or number), then its
+    /// `description` (or act) in parentheses, e.g.
+    /// `` `fill-email` (type into login form) `` or `3 (type)`.
+    pub fn label(&self, step: &str, act: &str) -> String {
+        let name = match &self.id {
+            Some(id) => format!("`{}`", id),
+            None => step.to_string(),
+        };
+        format!("{} ({})", name, self.description.as_deref().unwrap_or(act))
+    }
+}
+
+/// Number of the `i`th step under `prefix`: `3`, or `3.1` inside a block.
+pub fn step_id(prefix: &str, i: usize) -> String {
+    if prefix.is_empty() {
+        i.to_string()
+    } else {
+        format!("{}.{}", prefix, i)
+    }
+}
+
+/// Retry behaviour of a step; unset fields fall back to the list `defaults`.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, JsonSchema)]
+pub struct StepOptions {
+    /// the step fails once it has run this long, retries included: `wait`,
+    /// `wait_for`, `drag` and `keypress` repeats stop at it, other acts
+    /// finish their current attempt; also the `wait_for` timeout
+    pub timeout_ms: Option<u64>,
+    /// extra attempts after a failure
+    pub retries: Option<u32>,
+    pub retry_delay_ms: Option<u64>,
+}
+
+impl StepOptions {
+    /// `self`, with unset fields taken from `defaults`.
+    pub fn or(self, defaults: StepOptions) -> StepOptions {
+        StepOptions {
+            timeout_ms: self.timeout_ms.or(defaults.timeout_ms),
+            retries: self.retries.or(defaults.retries),
+            retry_delay_ms: self.retry_delay_ms.or(defaults.retry_delay_ms),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, JsonSchema)]
 #[serde(tag = "act")]
 pub enum Action {
     #[serde(rename = "launch")]
     Launch { target: String },
 
+    /// Opens an http(s) URL in the default browser, or in the app with
+    /// bundle id `browser`. The host is checked against the policy.
+    #[serde(rename = "open_url")]
+    OpenUrl {
+        url: String,
+        browser: Option<String>,
+    },
+
     #[serde(rename = "type")]
-    Type { text: String },
+    Type {
+        text: String,
+        #[serde(default)]
+        mode: TypeMode,
+        #[serde(default)]
+        layout: Layout,
+        /// focus this window first, so the text cannot go elsewhere
+        window: Option<WindowSelector>,
+    },
 
     #[serde(rename = "wait")]
     Wait { ms: u64 },
 
+    /// Times out after the step's `timeout_ms`.
+    #[serde(rename = "wait_for")]
+    WaitFor {
+        #[serde(flatten)]
+        cond: Condition,
+    },
+
+    /// Copies an element's (masked) value or label into `{var.<into>}`.
+    #[serde(rename = "read")]
+    Read {
+        selector: String,
+        #[serde(default)]
+        field: ReadField,
+        into: String,
+        window: Option<WindowSelector>,
+    },
+
+    /// Fails the job unless the window title matches `title` (glob) and/or
+    /// an element matches `selector` (with `equals` / `matches` on its value).
+    #[serde(rename = "assert")]
+    Assert {
+        selector: Option<String>,
+        window: Option<WindowSelector>,
+        title: Option<String>,
+        equals: Option<String>,
+        /// regex
+        matches: Option<String>,
+    },
+
+    /// Attaches a PNG of the screen to the job as an artifact.
+    #[serde(rename = "screenshot")]
+    Screenshot { name: Option<String> },
+
+    /// Attaches the masked UI tree of `window` (default: front) as an artifact.
+    #[serde(rename = "snapshot")]
+    Snapshot {
+        window: Option<WindowSelector>,
+        name: Option<String>,
+    },
+
     #[serde(rename = "click")]
     Click {
-        selector: Option<String>,
-        x: Option<i32>,
-        y: Option<i32>,
+        #[serde(flatten)]
+        at: Point,
+        #[serde(default)]
+        modifiers: Vec<Modifier>,
+    },
+
+    #[serde(rename = "double_click")]
+    DoubleClick {
+        #[serde(flatten)]
+        at: Point,
+        #[serde(default)]
+        modifiers: Vec<Modifier>,
+    },
+
+    #[serde(rename = "right_click")]
+    RightClick {
+        #[serde(flatten)]
+        at: Point,
+        #[serde(default)]
+        modifiers: Vec<Modifier>,
     },
 
+    #[serde(rename = "move_to")]
+    MoveTo {
+        #[serde(flatten)]
+        at: Point,
+    },
+
+    #[serde(rename = "drag")]
+    Drag {
+        from: Point,
+        to: Point,
+        #[serde(default)]
+        duration_ms: u64,
+        #[serde(default)]
+        modifiers: Vec<Modifier>,
+    },
+
+    /// Scroll-wheel events over `at` (default: wherever the pointer is).
+    /// Positive `dy` scrolls down, positive `dx` right.
     #[serde(rename = "scroll")]
-    Scroll { dy: i32 },
+    Scroll {
+        #[serde(default)]
+        dx: i32,
+        #[serde(default)]
+        dy: i32,
+        #[serde(default)]
+        unit: ScrollUnit,
+        #[serde(flatten)]
+        at: Point,
+    },
 
+    /// `key` is a key spec such as `"CMD+S"` or `"CMD+K, CMD+S"`; the whole
+    /// sequence is pressed `repeat` times.
     #[serde(rename = "keypress")]
-    Keypress { key: String },
+    Keypress { key: String, repeat: Option<u32> },
+
+    /// Puts `text` on the clipboard; `{secret.x}` is expanded server-side.
+    #[serde(rename = "clipboard_set")]
+    ClipboardSet { text: String },
+
+    /// Copies the (masked) clipboard text into `{var.<into>}`.
+    #[serde(rename = "clipboard_get")]
+    ClipboardGet { into: String },
+
+    #[serde(rename = "paste")]
+    Paste,
+
+    /// Performs the element's default action (AXPress) without moving the pointer.
+    #[serde(rename = "press")]
+    Press {
+        selector: String,
+        window: Option<WindowSelector>,
+    },
+
+    /// Writes `value` into a text field directly; `{secret.x}` is expanded
+    /// server-side.
+    #[serde(rename = "set_value")]
+    SetValue {
+        selector: String,
+        value: String,
+        window: Option<WindowSelector>,
+    },
+
+    /// Picks an item of the front app's menu bar, e.g. `"File > Export > PDF"`.
+    #[serde(rename = "menu")]
+    Menu { path: String },
+
+    /// Raises `window` and gives it keyboard focus.
+    #[serde(rename = "focus_window")]
+    FocusWindow { window: WindowSelector },
+
+    /// Moves the window's top-left corner to `x`/`y`.
+    #[serde(rename = "move_window")]
+    MoveWindow {
+        window: WindowSelector,
+        x: i32,
+        y: i32,
+    },
+
+    #[serde(rename = "resize_window")]
+    ResizeWindow {
+        window: WindowSelector,
+        width: u32,
+        height: u32,
+    },
+
+    #[serde(rename = "minimize")]
+    Minimize { window: WindowSelector },
+
+    /// Presses the window's close button.
+    #[serde(rename = "close_window")]
+    CloseWindow { window: WindowSelector },
+
+    /// Quits the app with bundle id `target`, unless it is in `protected_apps`.
+    #[serde(rename = "quit_app")]
+    QuitApp { target: String },
+
+    #[serde(rename = "if")]
+    If {
+        cond: Condition,
+        then: Vec<Step>,
+        #[serde(rename = "else", default)]
+        otherwise: Vec<Step>,
+    },
+
+    /// Runs `do` up to `times` times, stopping early once `while` no longer holds.
+    #[serde(rename = "repeat")]
+    Repeat {
+        times: u32,
+        #[serde(rename = "while")]
+        cond: Option<Condition>,
+        #[serde(rename = "do")]
+        body: Vec<Step>,
+    },
+
+    /// Named macro from `~/.thin-sag/macros`, expanded before validation.
+    #[serde(rename = "call")]
+    Call {
+        #[serde(rename = "macro")]
+        name: String,
+        #[serde(default)]
+        args: serde_json::Map<String, serde_json::Value>,
+    },
 
     #[serde(other)]
+    #[schemars(skip)]
     Unsupported,
 }
+
+/// Item titles of a `menu` path such as `"File > Export > PDF"`.
+pub fn menu_path(path: &str) -> Vec<&str> {
+    path.split('>').map(str::trim).collect()
+}
+
+/// Screen position, given either by coordinates or by an element selector
+/// resolved to the element's center at run time.
+#[derive(Deserialize, Debug, JsonSchema)]
+pub struct Point {
+    pub selector: Option<String>,
+    pub x: Option<i32>,
+    pub y: Option<i32>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ScrollUnit {
+    #[default]
+    Pixel,
+    Line,
+    /// the height (or width) of the target element, or of the main display
+    Page,
+}
+
+/// Modifier key held down during a pointer action.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Modifier {
+    #[serde(alias = "command")]
+    Cmd,
+    Shift,
+    #[serde(alias = "control")]
+    Ctrl,
+    #[serde(alias = "option", alias = "opt")]
+    Alt,
+}
+
+/// Predicate shared by `wait_for`, `if` and `repeat`: either a snapshot
+/// predicate on `selector` or a check of a captured `var`.
+#[derive(Deserialize, Debug, JsonSchema)]
+pub struct Condition {
+    pub selector: Option<String>,
+    pub var: Option<String>,
+    #[serde(default)]
+    pub state: WaitState,
+    /// regex for `value_matches`
+    pub value: Option<String>,
+    pub window: Option<WindowSelector>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WaitState {
+    #[default]
+    Exists,
+    Gone,
+    Enabled,
+    ValueMatches,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadField {
+    #[default]
+    Value,
+    Label,
+}
@@ -1,10 +1,46 @@
+use crate::action::{Modifier, Point, ScrollUnit};
+use crate::keymap::{Chord, Layout, TypeMode};
+use crate::tree::WindowSelector;
 use anyhow::Result;
 
 pub trait UiAdapter: Send + Sync + 'static {
     fn launch(&self, target: &str) -> Result<()>;
-    fn click(&self, selector: Option<&str>, x: Option<i32>, y: Option<i32>) -> Result<()>;
-    fn type_text(&self, text: &str) -> Result<()>;
-    fn scroll(&self, dy: i32) -> Result<()>;
-    fn keypress(&self, key: &str) -> Result<()>;
+    /// Open `url` in the default browser or the app with bundle id `browser`.
+    fn open_url(&self, url: &str, browser: Option<&str>) -> Result<()>;
+    fn click(&self, at: &Point, modifiers: &[Modifier]) -> Result<()>;
+    fn double_click(&self, at: &Point, modifiers: &[Modifier]) -> Result<()>;
+    fn right_click(&self, at: &Point, modifiers: &[Modifier]) -> Result<()>;
+    fn move_to(&self, at: &Point) -> Result<()>;
+    /// Stops early, releasing the button, once `stop` returns true.
+    fn drag(
+        &self,
+        from: &Point,
+        to: &Point,
+        duration_ms: u64,
+        modifiers: &[Modifier],
+        stop: &dyn Fn() -> bool,
+    ) -> Result<()>;
+    fn type_text(&self, text: &str, mode: TypeMode, layout: Layout) -> Result<()>;
+    fn scroll(&self, at: &Point, dx: i32, dy: i32, unit: ScrollUnit) -> Result<()>;
+    fn keypress(&self, chord: &Chord) -> Result<()>;
+    fn clipboard_set(&self, text: &str) -> Result<()>;
+    fn clipboard_get(&self) -> Result<String>;
+    fn paste(&self) -> Result<()>;
+    /// Perform the default action of the element matched by `selector`.
+    fn press(&self, selector: &str, window: &WindowSelector) -> Result<()>;
+    /// Write `value` into the element matched by `selector`.
+    fn set_value(&self, selector: &str, window: &WindowSelector, value: &str) -> Result<()>;
+    /// Pick the menu bar item at `path` (item titles, outermost first).
+    fn menu(&self, path: &[&str]) -> Result<()>;
+    /// Raise the window and give it keyboard focus.
+    fn focus_window(&self, window: &WindowSelector) -> Result<()>;
+    fn move_window(&self, window: &WindowSelector, x: i32, y: i32) -> Result<()>;
+    fn resize_window(&self, window: &WindowSelector, width: u32, height: u32) -> Result<()>;
+    fn minimize(&self, window: &WindowSelector) -> Result<()>;
+    fn close_window(&self, window: &WindowSelector) -> Result<()>;
+    /// Quit the app with bundle id `target`.
+    fn quit_app(&self, target: &str) -> Result<()>;
+    /// PNG of the whole desktop
+    fn screenshot(&self) -> Result<Vec<u8>>;
     fn wait_ms(&self, ms: u64);
 }
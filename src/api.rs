@@ -1,24 +1,39 @@
-use crate::policy::validate_actions;
+use crate::dryrun::dry_run;
+use crate::job::{prepare_actions, JobEvent, JobFilter, JobOrigin, JobPage, JobView};
+use crate::policy::{load as load_policy, take_snapshot_slot};
 use crate::screenshot::screenshot_handler;
+use crate::tree::snapshot_tree;
 use crate::tree::WindowSelector;
 use crate::tree::{list_windows_info, WindowInfo};
 use crate::{action::ActionList, error::ApiError, job::JobManager, models::*, tree::UiNode};
-use crate::{policy::load as load_policy, tree::snapshot_tree};
-use axum::http::StatusCode;
+use axum::extract::ws::{Message, WebSocketUpgrade};
+use axum::extract::Request;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     routing::{get, post},
     Json, Router,
 };
-use once_cell::sync::Lazy;
+use futures::stream::{self, Stream, StreamExt};
+use once_cell::sync::OnceCell;
 use serde::Deserialize;
-use std::sync::atomic::{AtomicU32, Ordering};
+use sha2::{Digest, Sha256};
+use std::convert::Infallible;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::time::{Duration, Instant};
+use tokio::sync::broadcast::{self, error::RecvError};
+use uuid::Uuid;
+
+const SAG_TOKEN_HEADER: &str = "x-sag-token";
+const SAG_TOKEN_FILE: &str = ".thin-sag/.sagtoken";
+const MAX_TAG_LEN: usize = 64;
 
-static SNAP_COUNT: Lazy<AtomicU32> = Lazy::new(|| AtomicU32::new(0));
-static SNAP_RESET: Lazy<tokio::sync::Mutex<Instant>> =
-    Lazy::new(|| tokio::sync::Mutex::new(Instant::now()));
 #[derive(Clone)]
 pub struct AppState {
     job_manager: Arc<JobManager>,
@@ -43,17 +58,7 @@ pub async fn snapshot_handler(Json(body): Json<WindowParam>) -> Result<Json<UiNo
     if !pol.allow_snapshot {
         return Err(ApiError::BadRequest(anyhow::anyhow!("snapshot disabled")));
     }
-    // rate‑limit
-    {
-        let mut guard = SNAP_RESET.lock().await;
-        if guard.elapsed() > Duration::from_secs(60) {
-            SNAP_COUNT.store(0, Ordering::SeqCst);
-            *guard = Instant::now();
-        }
-    }
-    if SNAP_COUNT.fetch_add(1, Ordering::SeqCst) >= pol.max_snapshot_per_min.unwrap_or(10) {
-        return Err(ApiError::BadRequest(anyhow::anyhow!("rate limit")));
-    }
+    take_snapshot_slot().map_err(ApiError::BadRequest)?;
 
     let sel = match body {
         WindowParam::Front(s) => {
@@ -77,9 +82,14 @@ pub async fn snapshot_handler(Json(body): Json<WindowParam>) -> Result<Json<UiNo
 
 pub async fn run_handler(
     State(st): State<Arc<AppState>>,
+    headers: HeaderMap,
     Json(req): Json<RunRequest>,
 ) -> Result<(axum::http::StatusCode, Json<serde_json::Value>), ApiError> {
-    let id = st.job_manager.enqueue(req).await;
+    let origin = JobOrigin {
+        tags: vec![],
+        agent: agent_of(&headers),
+    };
+    let id = st.job_manager.enqueue(req, origin).await?;
     Ok((
         axum::http::StatusCode::ACCEPTED,
         Json(serde_json::json!({"job_id": id})),
@@ -90,30 +100,226 @@ pub async fn run_handler(
 pub async fn job_status(
     State(st): State<Arc<AppState>>,
     Path(id): Path<String>,
-) -> Result<Json<serde_json::Value>, ApiError> {
+) -> Result<Json<JobView>, ApiError> {
     match st.job_manager.get(&id).await {
-        Some(r) => Ok(Json(serde_json::json!({
-            "status": format!("{:?}", r.status),
-            "result": r.output
-        }))),
+        Some(r) => Ok(Json(JobView::new(&id, &r))),
         None => Err(ApiError::NotFound(anyhow::anyhow!("Job ID 不明"))),
     }
 }
 
+/// The job as it is now (`job`), then its events up to and including
+/// `finished`. A job that has already finished yields just `finished`.
+fn job_events(
+    view: JobView,
+    rx: Option<broadcast::Receiver<JobEvent>>,
+) -> impl Stream<Item = serde_json::Value> {
+    let first = match rx {
+        Some(_) => {
+            let mut v = serde_json::to_value(&view).unwrap_or_default();
+            v["event"] = "job".into();
+            v
+        }
+        None => serde_json::to_value(JobEvent::Finished(Box::new(view))).unwrap_or_default(),
+    };
+    let rest = stream::unfold(rx, |rx| async move {
+        let mut rx = rx?;
+        match rx.recv().await {
+            Ok(event) => {
+                let last = matches!(event, JobEvent::Finished(_));
+                let v = serde_json::to_value(&event).unwrap_or_default();
+                Some((v, (!last).then_some(rx)))
+            }
+            Err(RecvError::Lagged(missed)) => Some((
+                serde_json::json!({"event": "lagged", "missed": missed}),
+                Some(rx),
+            )),
+            Err(RecvError::Closed) => None,
+        }
+    });
+    stream::iter([first]).chain(rest)
+}
+
+/// GET /job/{id}/events
+///
+/// Server-Sent Events; each event's name matches its `event` field.
+pub async fn job_events_sse(
+    State(st): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let (view, rx) = st
+        .job_manager
+        .watch(&id)
+        .await
+        .ok_or_else(|| ApiError::NotFound(anyhow::anyhow!("Job ID 不明")))?;
+    let events = job_events(view, rx).map(|v| {
+        let name = v["event"].as_str().unwrap_or("message").to_string();
+        Ok(Event::default().event(name).data(v.to_string()))
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// GET /job/{id}/ws
+///
+/// Same events as `/job/{id}/events`, one JSON text message each; the
+/// socket is closed after `finished`.
+pub async fn job_events_ws(
+    State(st): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    ws: WebSocketUpgrade,
+) -> Result<impl IntoResponse, ApiError> {
+    let (view, rx) = st
+        .job_manager
+        .watch(&id)
+        .await
+        .ok_or_else(|| ApiError::NotFound(anyhow::anyhow!("Job ID 不明")))?;
+    Ok(ws.on_upgrade(move |mut socket| async move {
+        let mut events = std::pin::pin!(job_events(view, rx));
+        while let Some(v) = events.next().await {
+            if socket.send(Message::Text(v.to_string())).await.is_err() {
+                return;
+            }
+        }
+        let _ = socket.send(Message::Close(None)).await;
+    }))
+}
+
+/// GET /job/{id}/artifacts/{n}
+pub async fn job_artifact(
+    State(st): State<Arc<AppState>>,
+    Path((id, n)): Path<(String, usize)>,
+) -> Result<impl IntoResponse, ApiError> {
+    let job = st
+        .job_manager
+        .get(&id)
+        .await
+        .ok_or_else(|| ApiError::NotFound(anyhow::anyhow!("Job ID 不明")))?;
+    let art = job
+        .artifacts
+        .get(n)
+        .ok_or_else(|| ApiError::NotFound(anyhow::anyhow!("artifact {} not found", n)))?;
+    Ok((
+        [(header::CONTENT_TYPE, art.content_type.clone())],
+        art.data.as_ref().clone(),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct RunJsonParams {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// Fingerprint of the `X-SAG-TOKEN` header: enough to tell agents apart in
+/// `GET /jobs` without keeping the token itself.
+fn agent_of(headers: &HeaderMap) -> Option<String> {
+    let token = headers.get(SAG_TOKEN_HEADER)?.as_bytes();
+    let digest = format!("{:x}", Sha256::digest(token));
+    Some(digest[..16].to_string())
+}
+
+/// `tags` of a `/run-json` body: short, non-empty strings.
+fn parse_tags(value: serde_json::Value) -> Result<Vec<String>, ApiError> {
+    let tags: Vec<String> = serde_json::from_value(value)
+        .map_err(|e| ApiError::BadRequest(anyhow::anyhow!("invalid tags: {}", e)))?;
+    if let Some(bad) = tags.iter().find(|t| t.is_empty() || t.len() > MAX_TAG_LEN) {
+        return Err(ApiError::BadRequest(anyhow::anyhow!(
+            "tag `{}` must be 1-{} bytes",
+            bad,
+            MAX_TAG_LEN
+        )));
+    }
+    Ok(tags)
+}
+
+/// POST /run-json
+///
+/// With `?dry_run=true` nothing is queued: the list goes through the whole
+/// submission pipeline and the per-step report is returned. The body may
+/// then also carry a `snapshot` (same shape as `/snapshot` output) to
+/// resolve selectors against instead of the live desktop. An object body
+/// may carry `tags` to find the job by in `GET /jobs`.
 pub async fn run_json(
     State(st): State<Arc<AppState>>,
-    Json(list): Json<ActionList>,
+    Query(params): Query<RunJsonParams>,
+    headers: HeaderMap,
+    Json(mut body): Json<serde_json::Value>,
 ) -> Result<(StatusCode, Json<serde_json::Value>), ApiError> {
-    if let Err(e) = validate_actions(&list) {
-        return Err(ApiError::BadRequest(e));
+    let tags = match body.as_object_mut().and_then(|o| o.remove("tags")) {
+        Some(v) => parse_tags(v)?,
+        None => vec![],
+    };
+    let snapshot = match body.as_object_mut().and_then(|o| o.remove("snapshot")) {
+        Some(v) if params.dry_run => Some(
+            serde_json::from_value::<UiNode>(v)
+                .map_err(|e| ApiError::BadRequest(anyhow::anyhow!("invalid snapshot: {}", e)))?,
+        ),
+        _ => None,
+    };
+    let list: ActionList =
+        serde_json::from_value(body).map_err(|e| ApiError::BadRequest(e.into()))?;
+    if params.dry_run {
+        // AX snapshots and Keychain lookups block, like a job's steps
+        let report = tokio::task::spawn_blocking(move || dry_run(list, snapshot))
+            .await
+            .map_err(|e| ApiError::Internal(e.into()))?;
+        return Ok((StatusCode::OK, Json(serde_json::to_value(report)?)));
     }
-    let id = st.job_manager.enqueue_json(list).await;
+    let list = prepare_actions(list).map_err(ApiError::BadRequest)?;
+    let origin = JobOrigin {
+        tags,
+        agent: agent_of(&headers),
+    };
+    let id = st.job_manager.enqueue_json(list, origin).await?;
     Ok((
         StatusCode::ACCEPTED,
         Json(serde_json::json!({"job_id": id})),
     ))
 }
 
+/// GET /jobs
+pub async fn list_jobs(
+    State(st): State<Arc<AppState>>,
+    Query(filter): Query<JobFilter>,
+) -> Json<JobPage> {
+    Json(st.job_manager.list(&filter).await)
+}
+
+/// DELETE /job/{id}
+///
+/// A queued job is cancelled at once; a running one stops at its next step
+/// (or wait slice) and reports `Cancelled` when it does.
+pub async fn cancel_job(
+    State(st): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<serde_json::Value>), ApiError> {
+    let status = st.job_manager.cancel(&id).await?;
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({"job_id": id, "status": status})),
+    ))
+}
+
+/// POST /halt
+///
+/// Emergency stop: cancels the running job, drains the queue and refuses
+/// new jobs until `POST /resume`.
+pub async fn halt_handler(State(st): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    let cancelled = st.job_manager.halt().await;
+    Json(serde_json::json!({"halted": true, "cancelled": cancelled}))
+}
+
+/// POST /resume
+pub async fn resume_handler(State(st): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    st.job_manager.resume();
+    Json(serde_json::json!({"halted": false}))
+}
+
+/// GET /schema/actions
+pub async fn schema_handler() -> Result<Json<serde_json::Value>, ApiError> {
+    let pol = load_policy().map_err(ApiError::Internal)?;
+    Ok(Json(crate::schema::export(&pol)))
+}
+
 /// GET /windows
 pub async fn windows_handler() -> Json<Vec<WindowInfo>> {
     eprintln!("[DEBUG] /windows called");
@@ -121,16 +327,108 @@ pub async fn windows_handler() -> Json<Vec<WindowInfo>> {
     Json(list)
 }
 
+static SAG_TOKEN: OnceCell<String> = OnceCell::new();
+
+/// The API token in `~/.thin-sag/.sagtoken`, generated (readable by the
+/// owner only) if the file is missing or empty.
+fn sag_token() -> anyhow::Result<&'static str> {
+    SAG_TOKEN
+        .get_or_try_init(|| {
+            let path = dirs::home_dir()
+                .unwrap_or(PathBuf::from("/"))
+                .join(SAG_TOKEN_FILE);
+            if let Ok(token) = fs::read_to_string(&path) {
+                if !token.trim().is_empty() {
+                    return Ok(token.trim().to_string());
+                }
+            }
+            let token = Uuid::new_v4().simple().to_string();
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .mode(0o600)
+                .open(&path)?
+                .write_all(token.as_bytes())?;
+            Ok(token)
+        })
+        .map(String::as_str)
+}
+
+/// Rejects requests without the `X-SAG-TOKEN` from `~/.thin-sag/.sagtoken`.
+async fn require_token(req: Request, next: Next) -> Result<Response, ApiError> {
+    let token = sag_token().map_err(ApiError::Internal)?;
+    match req.headers().get(SAG_TOKEN_HEADER) {
+        Some(sent) if sent.as_bytes() == token.as_bytes() => Ok(next.run(req).await),
+        _ => Err(ApiError::Unauthorized(anyhow::anyhow!(
+            "missing or wrong X-SAG-TOKEN"
+        ))),
+    }
+}
+
 pub fn build_router() -> Router {
     let state = Arc::new(AppState {
         job_manager: Arc::new(JobManager::new()),
     });
+    // create the token file up front, for clients to read
+    if let Err(e) = sag_token() {
+        eprintln!("[auth] {:#}; token-protected routes will fail", e);
+    }
     Router::new()
         .route("/run", post(run_handler))
-        .route("/job/:id", get(job_status))
+        .route("/jobs", get(list_jobs))
+        .route("/job/:id", get(job_status).delete(cancel_job))
+        .route("/job/:id/events", get(job_events_sse))
+        .route("/job/:id/ws", get(job_events_ws))
+        .route("/job/:id/artifacts/:n", get(job_artifact))
         .route("/run-json", post(run_json))
+        .route("/halt", post(halt_handler))
+        .route("/resume", post(resume_handler))
         .route("/snapshot", post(snapshot_handler))
-        .route("/windows", get(windows_handler))
+        .route("/schema/actions", get(schema_handler))
         .route("/screenshot", get(screenshot_handler))
+        // applies to the routes above only
+        .route_layer(middleware::from_fn(require_token))
+        .route("/windows", get(windows_handler))
         .with_state(state)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::StepMeta;
+    use crate::job::{JobResult, JobStatus, StepRecord};
+
+    #[tokio::test]
+    async fn test_job_events_end_with_finished() {
+        let view = JobView::new("j", &JobResult::pending());
+        let step = StepRecord {
+            step: "0".into(),
+            act: "click".into(),
+            meta: StepMeta::default(),
+            status: JobStatus::Running,
+            started_at: chrono::Utc::now(),
+            duration_ms: 0,
+            attempts: 0,
+            error: None,
+        };
+        let (tx, rx) = broadcast::channel(8);
+        tx.send(JobEvent::StepStarted(step.clone())).unwrap();
+        tx.send(JobEvent::Finished(Box::new(view.clone()))).unwrap();
+        tx.send(JobEvent::StepStarted(step)).unwrap();
+        let names: Vec<_> = job_events(view.clone(), Some(rx))
+            .map(|v| v["event"].as_str().unwrap().to_string())
+            .collect()
+            .await;
+        assert_eq!(names, ["job", "step_started", "finished"]);
+
+        // a finished job has nothing more to say
+        let events: Vec<_> = job_events(view, None).collect().await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["event"], "finished");
+        assert_eq!(events[0]["job_id"], "j");
+    }
+}
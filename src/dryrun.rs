@@ -0,0 +1,317 @@
+// src/dryrun.rs
+
+use crate::action::{step_id, Action, ActionList, Condition, Point, Step, StepMeta};
+use crate::job::{SECRET_REGEX, VAR_REGEX};
+use crate::macros::expand_macros;
+use crate::policy::{self, act_name};
+use crate::selector::Selector;
+use crate::tree::{snapshot_tree, UiNode, WindowSelector};
+use crate::vault;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+
+/// What `/run-json?dry_run=true` and `thin-sag exec --dry-run` report.
+#[derive(Debug, Serialize)]
+pub struct DryRunReport {
+    /// true when nothing below is expected to fail
+    pub ok: bool,
+    /// macro expansion or policy error for the list as a whole
+    pub error: Option<String>,
+    pub steps: Vec<DryRunStep>,
+}
+
+/// One step with what was checked. Blocks are listed before their steps,
+/// with both branches of an `if` and one pass over a `repeat` body.
+#[derive(Debug, Serialize)]
+pub struct DryRunStep {
+    pub step: String,
+    pub act: &'static str,
+    #[serde(flatten)]
+    pub meta: StepMeta,
+    /// what the step would act on
+    pub notes: Vec<String>,
+    /// why the step would fail
+    pub errors: Vec<String>,
+}
+
+/// Does a selector have to match exactly one element, or is it a condition
+/// that may legitimately match nothing yet?
+#[derive(Clone, Copy, PartialEq)]
+enum Need {
+    One,
+    Any,
+}
+
+/// Run the whole submission pipeline without touching input devices:
+/// macro expansion, policy validation, secret references (existence only),
+/// `{var.x}` references and selectors, resolved against `snapshot` if given
+/// or else a live snapshot of each step's window.
+pub fn dry_run(list: ActionList, snapshot: Option<UiNode>) -> DryRunReport {
+    simulate(list, snapshot, &vault::has_secret)
+}
+
+fn simulate(
+    list: ActionList,
+    snapshot: Option<UiNode>,
+    has_secret: &dyn Fn(&str) -> bool,
+) -> DryRunReport {
+    let list = match expand_macros(list) {
+        Ok(list) => list,
+        Err(e) => {
+            return DryRunReport {
+                ok: false,
+                error: Some(e.to_string()),
+                steps: vec![],
+            }
+        }
+    };
+    let error = policy::validate_actions(&list).err().map(|e| e.to_string());
+    let mut sim = Simulator {
+        supplied: snapshot,
+        live: HashMap::new(),
+        has_secret,
+        vars: BTreeSet::new(),
+        steps: Vec::new(),
+    };
+    sim.walk(&list.actions, "");
+    DryRunReport {
+        ok: error.is_none() && sim.steps.iter().all(|s| s.errors.is_empty()),
+        error,
+        steps: sim.steps,
+    }
+}
+
+struct Simulator<'a> {
+    supplied: Option<UiNode>,
+    /// live snapshots by window, taken once per dry run
+    live: HashMap<String, Result<UiNode, String>>,
+    has_secret: &'a dyn Fn(&str) -> bool,
+    /// variables captured by the steps seen so far
+    vars: BTreeSet<String>,
+    steps: Vec<DryRunStep>,
+}
+
+impl Simulator<'_> {
+    fn walk(&mut self, actions: &[Step], prefix: &str) {
+        for (i, Step { meta, act, .. }) in actions.iter().enumerate() {
+            let step = step_id(prefix, i);
+            let mut report = DryRunStep {
+                step: step.clone(),
+                act: act_name(act),
+                meta: meta.clone(),
+                notes: vec![],
+                errors: vec![],
+            };
+            for (selector, window, need) in selectors(act) {
+                self.check_selector(selector, window, need, &mut report);
+            }
+            match act {
+                Action::If {
+                    then, otherwise, ..
+                } => {
+                    self.steps.push(report);
+                    self.walk(then, &step);
+                    self.walk(otherwise, &step);
+                    continue;
+                }
+                Action::Repeat { body, .. } => {
+                    self.steps.push(report);
+                    self.walk(body, &step);
+                    continue;
+                }
+                _ => {}
+            }
+            for text in texts(act) {
+                self.check_text(text, &mut report);
+            }
+            if let Action::Read { into, .. } | Action::ClipboardGet { into } = act {
+                self.vars.insert(into.clone());
+            }
+            self.steps.push(report);
+        }
+    }
+
+    /// `{secret.x}` must exist in the Keychain and `{var.x}` must be
+    /// captured by an earlier step. Secret values are never read.
+    fn check_text(&self, text: &str, report: &mut DryRunStep) {
+        for caps in SECRET_REGEX.captures_iter(text) {
+            if !(self.has_secret)(&caps[1]) {
+                report
+                    .errors
+                    .push(format!("secret `{}` not found in Keychain", &caps[1]));
+            }
+        }
+        for caps in VAR_REGEX.captures_iter(text) {
+            if !self.vars.contains(&caps[1]) {
+                report.errors.push(format!(
+                    "variable `{}` is not set by an earlier step",
+                    &caps[1]
+                ));
+            }
+        }
+    }
+
+    fn check_selector(
+        &mut self,
+        selector: &str,
+        window: Option<&WindowSelector>,
+        need: Need,
+        report: &mut DryRunStep,
+    ) {
+        let Ok(sel) = Selector::parse(selector) else {
+            // already reported by the policy check
+            return;
+        };
+        let tree = match self.snapshot(window) {
+            Ok(tree) => tree,
+            Err(e) => {
+                report
+                    .notes
+                    .push(format!("selector `{}` not checked: {}", selector, e));
+                return;
+            }
+        };
+        let (hits, total) = sel.find_all(tree);
+        match (need, hits.as_slice()) {
+            (Need::One, [node]) => report.notes.push(format!(
+                "selector `{}` → {} \"{}\"{}",
+                selector,
+                node.role,
+                node.label,
+                node.rect
+                    .as_ref()
+                    .map(|r| {
+                        let (x, y) = r.center();
+                        format!(" at ({:.0},{:.0})", x, y)
+                    })
+                    .unwrap_or_default()
+            )),
+            (Need::One, _) => report.errors.push(format!(
+                "selector `{}` matched {}/{} elements",
+                selector,
+                hits.len(),
+                total
+            )),
+            (Need::Any, _) => report.notes.push(format!(
+                "selector `{}` matches {}/{} elements now",
+                selector,
+                hits.len(),
+                total
+            )),
+        }
+    }
+
+    fn snapshot(&mut self, window: Option<&WindowSelector>) -> Result<&UiNode, String> {
+        if let Some(tree) = &self.supplied {
+            return Ok(tree);
+        }
+        let window = window.cloned().unwrap_or(WindowSelector::Front);
+        self.live
+            .entry(format!("{:?}", window))
+            .or_insert_with(|| snapshot_tree(window).map_err(|e| e.to_string()))
+            .as_ref()
+            .map_err(Clone::clone)
+    }
+}
+
+/// Texts that may reference `{secret.x}` / `{var.x}`.
+fn texts(act: &Action) -> Vec<&str> {
+    match act {
+        Action::Type { text, .. } | Action::ClipboardSet { text } => vec![text],
+        Action::SetValue { value, .. } => vec![value],
+        _ => vec![],
+    }
+}
+
+type Target<'a> = (&'a str, Option<&'a WindowSelector>, Need);
+
+/// Every selector a step evaluates, with the window it is evaluated in.
+fn selectors(act: &Action) -> Vec<Target<'_>> {
+    fn point(p: &Point) -> Option<&str> {
+        p.selector.as_deref()
+    }
+    fn cond(c: &Condition) -> Option<Target<'_>> {
+        c.selector
+            .as_deref()
+            .map(|s| (s, c.window.as_ref(), Need::Any))
+    }
+    match act {
+        Action::Click { at, .. }
+        | Action::DoubleClick { at, .. }
+        | Action::RightClick { at, .. }
+        | Action::MoveTo { at }
+        | Action::Scroll { at, .. } => point(at)
+            .map(|s| (s, None, Need::One))
+            .into_iter()
+            .collect(),
+        Action::Drag { from, to, .. } => [point(from), point(to)]
+            .into_iter()
+            .flatten()
+            .map(|s| (s, None, Need::One))
+            .collect(),
+        Action::Read {
+            selector, window, ..
+        }
+        | Action::Press { selector, window }
+        | Action::SetValue {
+            selector, window, ..
+        } => vec![(selector, window.as_ref(), Need::One)],
+        Action::Assert {
+            selector: Some(selector),
+            window,
+            ..
+        } => vec![(selector, window.as_ref(), Need::Any)],
+        Action::WaitFor { cond: c, .. } | Action::If { cond: c, .. } => {
+            cond(c).into_iter().collect()
+        }
+        Action::Repeat { cond: Some(c), .. } => cond(c).into_iter().collect(),
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::node;
+
+    #[test]
+    fn test_dry_run_checks_secrets_vars_and_selectors() {
+        let list: ActionList = serde_json::from_str(
+            r#"[{"act":"type","text":"{secret.known} {secret.unknown}","id":"login"},
+                {"act":"click","selector":"AXButton[label=\"Save\"]"},
+                {"act":"press","selector":"AXButton"},
+                {"act":"if","cond":{"selector":"AXSheet"},"then":[
+                  {"act":"clipboard_get","into":"copied"}]},
+                {"act":"type","text":"{var.copied} {var.later}"}]"#,
+        )
+        .unwrap();
+        let tree = node(
+            "AXWindow",
+            "Mail",
+            None,
+            vec![
+                node("AXButton", "Save", None, vec![]),
+                node("AXButton", "Cancel", None, vec![]),
+            ],
+        );
+        let report = simulate(list, Some(tree), &|label| label == "known");
+        assert!(!report.ok);
+        assert_eq!(report.error, None);
+        let steps: Vec<_> = report.steps.iter().map(|s| s.step.as_str()).collect();
+        assert_eq!(steps, ["0", "1", "2", "3", "3.0", "4"]);
+
+        assert_eq!(report.steps[0].meta.id.as_deref(), Some("login"));
+        assert_eq!(
+            report.steps[0].errors,
+            ["secret `unknown` not found in Keychain"]
+        );
+        assert!(report.steps[1].errors.is_empty());
+        assert!(report.steps[1].notes[0].contains("AXButton \"Save\""));
+        assert!(report.steps[2].errors[0].contains("matched 2/3"));
+        assert!(report.steps[3].notes[0].contains("matches 0/3"));
+        assert_eq!(
+            report.steps[5].errors,
+            ["variable `later` is not set by an earlier step"]
+        );
+    }
+}
@@ -0,0 +1,67 @@
+// src/element.rs
+
+use crate::selector::resolve_element;
+use crate::tree::{children, front_app, get_attr, string_attr, WindowSelector};
+use crate::window::{perform, set_attr};
+use anyhow::{anyhow, Result};
+use core_foundation::base::TCFType;
+use core_foundation::string::CFString;
+use globset::{Glob, GlobMatcher};
+use objc::runtime::Object;
+
+/// Performs the default action (AXPress) of the element matched by `selector`.
+pub fn press(selector: &str, window: &WindowSelector) -> Result<()> {
+    perform(resolve_element(selector, window)?, "AXPress")
+}
+
+/// Writes `value` into the element's AXValue, replacing its whole content.
+pub fn set_value(selector: &str, window: &WindowSelector, value: &str) -> Result<()> {
+    let element = resolve_element(selector, window)?;
+    set_attr(element, "AXValue", CFString::new(value).as_CFTypeRef())
+}
+
+/// Presses the menu item at `path` (`["File", "Export", "PDF"]`) in the
+/// front app's menu bar. Each name is a glob on the item title; a trailing
+/// ellipsis on the title is ignored.
+pub fn pick_menu(path: &[&str]) -> Result<()> {
+    let bar = get_attr(front_app()?, "AXMenuBar")
+        .ok_or_else(|| anyhow!("front app has no menu bar"))? as *mut Object;
+    let mut element = bar;
+    for (depth, name) in path.iter().enumerate() {
+        let g = Glob::new(name)?.compile_matcher();
+        element = menu_items(element)
+            .into_iter()
+            .find(|item| string_attr(*item, "AXTitle").is_some_and(|t| title_matches(&g, &t)))
+            .ok_or_else(|| anyhow!("menu item `{}` not found", path[..=depth].join(" > ")))?;
+    }
+    perform(element, "AXPress")
+}
+
+/// Items under a menu bar, or under a menu item's submenu.
+fn menu_items(element: *mut Object) -> Vec<*mut Object> {
+    let kids = children(element);
+    match kids.as_slice() {
+        [menu] if string_attr(*menu, "AXRole").as_deref() == Some("AXMenu") => children(*menu),
+        _ => kids,
+    }
+}
+
+fn title_matches(g: &GlobMatcher, title: &str) -> bool {
+    g.is_match(title) || g.is_match(title.trim_end_matches(['…', '.']))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_menu_title_ignores_ellipsis() {
+        let g = Glob::new("Export").unwrap().compile_matcher();
+        assert!(title_matches(&g, "Export"));
+        assert!(title_matches(&g, "Export…"));
+        assert!(title_matches(&g, "Export..."));
+        assert!(!title_matches(&g, "Export as PDF…"));
+        let g = Glob::new("Export as*").unwrap().compile_matcher();
+        assert!(title_matches(&g, "Export as PDF…"));
+    }
+}
@@ -15,6 +15,7 @@ pub struct ErrorBody {
 #[derive(Debug)]
 pub enum ApiError {
     BadRequest(Error),
+    Unauthorized(Error),
     NotFound(Error),
     Conflict(Error),
     Internal(Error),
@@ -37,6 +38,14 @@ impl IntoResponse for ApiError {
                 }),
             )
                 .into_response(),
+            ApiError::Unauthorized(e) => (
+                StatusCode::UNAUTHORIZED,
+                Json(ErrorBody {
+                    ok: false,
+                    error: e.to_string(),
+                }),
+            )
+                .into_response(),
             ApiError::NotFound(e) => (
                 StatusCode::NOT_FOUND,
                 Json(ErrorBody {
@@ -71,6 +80,7 @@ impl fmt::Display for ApiError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             ApiError::BadRequest(e) => write!(f, "BadRequest: {}", e),
+            ApiError::Unauthorized(e) => write!(f, "Unauthorized: {}", e),
             ApiError::NotFound(e) => write!(f, "NotFound: {}", e),
             ApiError::Conflict(e) => write!(f, "Conflict: {}", e),
             ApiError::Internal(e) => write!(f, "Internal: {}", e),
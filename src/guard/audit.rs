@@ -3,9 +3,21 @@ use crate::error::ApiError;
 use crate::guard::ipc::UserDecision;
 use crate::guard::GuardEvent;
 use chrono::Utc;
-use serde_json::json;
+use serde_json::{json, Value};
 use std::fs::{create_dir_all, OpenOptions};
 use std::io::Write;
+use std::path::PathBuf;
+
+/// Append one JSON line to today's audit log.
+pub fn append(entry: &Value) -> std::io::Result<()> {
+    let dir = dirs::home_dir()
+        .unwrap_or(PathBuf::from("/"))
+        .join(".thin-sag/logs");
+    create_dir_all(&dir)?;
+    let file = dir.join(format!("audit-{}.log", Utc::now().format("%Y%m%d")));
+    let mut f = OpenOptions::new().create(true).append(true).open(file)?;
+    writeln!(f, "{}", entry)
+}
 
 /// Write audit logs in JSONL format
 pub async fn write(
@@ -13,15 +25,6 @@ pub async fn write(
     rule_id: &str,
     decision: &UserDecision,
 ) -> Result<(), ApiError> {
-    let dir = dirs::home_dir().unwrap().join(".thin-sag/logs");
-    create_dir_all(&dir).unwrap();
-    let file = dir.join(format!("audit-{}.log", Utc::now().format("%Y%m%d")));
-    let mut f = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(file)
-        .map_err(|e| ApiError::Internal(e.into()))?;
-
     let entry = json!({
         "ts": Utc::now().timestamp(),
         "pid": event.pid,
@@ -32,8 +35,7 @@ pub async fn write(
             UserDecision::Deny => "deny",
         }
     });
-    writeln!(f, "{}", entry.to_string()).unwrap();
-    Ok(())
+    append(&entry).map_err(|e| ApiError::Internal(e.into()))
 }
 
 #[cfg(test)]
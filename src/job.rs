@@ -1,39 +1,568 @@
-use crate::mask::register_secret;
+use crate::macros::expand_macros;
+use crate::mask::{mask_text, register_secret};
 use crate::{
-    action::{Action, ActionList},
+    action::{
+        menu_path, step_id, Action, ActionList, Condition, ReadField, Step, StepMeta, StepOptions,
+        WaitState,
+    },
     adapter::UiAdapter,
     error::ApiError,
+    guard::audit,
+    keymap::{parse_keys, Layout, TypeMode},
     mac_ax::MacAdapter,
     models::{RunRequest, RunResponse},
+    policy::{self, act_name},
+    selector::Selector,
+    store::{JobStore, JsonlStore, MemoryStore},
+    tree::{snapshot_tree, UiNode, WindowSelector},
     vault,
 };
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use globset::Glob;
 use lazy_static::lazy_static;
 use regex::Regex;
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::mpsc;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
 use tokio::sync::RwLock;
+use tokio::sync::{broadcast, mpsc};
 use uuid::Uuid;
 
 lazy_static! {
-    static ref SECRET_REGEX: Regex = Regex::new(r"\{secret\.([a-zA-Z0-9_-]+)\}").unwrap();
+    pub(crate) static ref SECRET_REGEX: Regex =
+        Regex::new(r"\{secret\.([a-zA-Z0-9_-]+)\}").unwrap();
+    pub(crate) static ref VAR_REGEX: Regex = Regex::new(r"\{var\.([a-zA-Z0-9_-]+)\}").unwrap();
 }
 
-#[derive(Clone, Copy, serde::Serialize, Debug)]
+const POLL_INTERVAL_MS: u64 = 250;
+const DEFAULT_WAIT_FOR_MS: u64 = 10_000;
+/// events a slow watcher may fall behind by before it misses some
+const EVENT_BUFFER: usize = 256;
+const DEFAULT_PAGE: usize = 50;
+const MAX_PAGE: usize = 500;
+/// how often evicted jobs are dropped from the store; the map is also
+/// trimmed on every submission
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const SNAPSHOT_TRIM_DEPTH: usize = 3;
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
 pub enum JobStatus {
     Pending,
     Running,
     Success,
     Failed,
+    /// was pending or running when the server stopped
+    Interrupted,
+    /// stopped by `DELETE /job/{id}` or `POST /halt`
+    Cancelled,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct JobResult {
     pub status: JobStatus,
     pub output: Option<String>,
+    /// values captured by `read` steps
+    #[serde(default)]
+    pub vars: BTreeMap<String, String>,
+    pub failure: Option<StepFailure>,
+    /// per-step outcome, including retry attempts
+    #[serde(default)]
+    pub steps: Vec<StepRecord>,
+    /// step being executed while the job is `Running`
+    #[serde(default)]
+    pub current_step: Option<String>,
+    #[serde(default)]
+    pub artifacts: Vec<Artifact>,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// fingerprint of the submitter's agent token
+    #[serde(default)]
+    pub agent: Option<String>,
+    /// set to ask the worker to stop the job at the next check
+    #[serde(skip)]
+    cancel: Arc<AtomicBool>,
+}
+
+impl JobResult {
+    pub fn pending() -> Self {
+        Self {
+            status: JobStatus::Pending,
+            output: None,
+            vars: BTreeMap::new(),
+            failure: None,
+            steps: Vec::new(),
+            current_step: None,
+            artifacts: Vec::new(),
+            created_at: Utc::now(),
+            started_at: None,
+            finished_at: None,
+            tags: Vec::new(),
+            agent: None,
+            cancel: Arc::default(),
+        }
+    }
+
+    /// Fold a progress event from the worker into the job.
+    pub fn apply(&mut self, event: &JobEvent) {
+        match event {
+            JobEvent::StepStarted(record) => {
+                self.current_step = Some(record.step.clone());
+                self.steps.push(record.clone());
+            }
+            JobEvent::StepFinished(record) => {
+                self.current_step = None;
+                match self.steps.last_mut() {
+                    Some(last) if last.step == record.step => *last = record.clone(),
+                    _ => self.steps.push(record.clone()),
+                }
+            }
+            JobEvent::Artifact { artifact, .. } => self.artifacts.push(artifact.clone()),
+            JobEvent::Finished(_) => {}
+        }
+    }
+}
+
+/// Why a job stopped: the failing step and, for `assert`, what was
+/// expected, what was found and the relevant part of the UI tree.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StepFailure {
+    /// position in the action list, `parent.child` inside blocks
+    pub step: String,
+    pub act: String,
+    #[serde(flatten)]
+    pub meta: StepMeta,
+    pub error: String,
+    pub expected: Option<String>,
+    pub actual: Option<String>,
+    pub snapshot: Option<Box<UiNode>>,
+}
+
+impl StepFailure {
+    fn new(step: &str, meta: &StepMeta, act: &Action, err: anyhow::Error) -> Self {
+        let mut failure = Self {
+            step: step.to_string(),
+            act: act_name(act).to_string(),
+            meta: meta.clone(),
+            error: mask_text(err.to_string()),
+            expected: None,
+            actual: None,
+            snapshot: None,
+        };
+        if let Some(a) = err.downcast_ref::<AssertionFailed>() {
+            failure.expected = Some(mask_text(&a.expected));
+            failure.actual = Some(mask_text(&a.actual));
+            failure.snapshot = a.snapshot.as_ref().map(|s| Box::new(s.masked()));
+        }
+        failure
+    }
+}
+
+impl fmt::Display for StepFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "step {}: {}",
+            self.meta.label(&self.step, &self.act),
+            self.error
+        )
+    }
+}
+
+#[derive(Debug)]
+struct AssertionFailed {
+    expected: String,
+    actual: String,
+    snapshot: Option<Box<UiNode>>,
+}
+
+impl fmt::Display for AssertionFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "assertion failed: expected {}, got {}",
+            self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for AssertionFailed {}
+
+/// Outcome of one executed (non-block) step. While the step runs its
+/// record has status `Running` and no duration yet.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StepRecord {
+    /// position in the action list, `parent.child` inside blocks
+    pub step: String,
+    pub act: String,
+    #[serde(flatten)]
+    pub meta: StepMeta,
+    /// `Running`, `Success`, `Failed` or `Cancelled`
+    pub status: JobStatus,
+    pub started_at: DateTime<Utc>,
+    pub duration_ms: u64,
+    pub attempts: u32,
+    /// masked error text when the step failed
+    pub error: Option<String>,
+}
+
+/// Progress reported while a job runs, through `JobContext::on_event`, and
+/// streamed by `GET /job/{id}/events`.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JobEvent {
+    StepStarted(StepRecord),
+    StepFinished(StepRecord),
+    /// `n` is the index for `GET /job/{id}/artifacts/{n}`
+    Artifact {
+        n: usize,
+        bytes: usize,
+        #[serde(flatten)]
+        artifact: Artifact,
+    },
+    /// the job reached its final status; always the last event
+    Finished(Box<JobView>),
+}
+
+impl fmt::Display for StepRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = self.meta.label(&self.step, &self.act);
+        match &self.error {
+            None => write!(f, "step {}: ok in {}ms", label, self.duration_ms)?,
+            Some(e) => write!(f, "step {}: failed: {}", label, e)?,
+        }
+        if self.attempts > 1 {
+            write!(f, " ({} attempts)", self.attempts)?;
+        }
+        Ok(())
+    }
+}
+
+/// What `GET /job/{id}` returns.
+#[derive(Clone, Debug, Serialize)]
+pub struct JobView {
+    pub job_id: String,
+    pub status: JobStatus,
+    /// summary message once the job has finished
+    pub result: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// from start to finish, or to now while running
+    pub duration_ms: Option<u64>,
+    pub tags: Vec<String>,
+    pub agent: Option<String>,
+    pub current_step: Option<String>,
+    pub steps: Vec<StepRecord>,
+    pub vars: BTreeMap<String, String>,
+    pub failure: Option<StepFailure>,
+    pub artifacts: Vec<ArtifactInfo>,
+}
+
+/// An artifact without its data, which is served separately.
+#[derive(Clone, Debug, Serialize)]
+pub struct ArtifactInfo {
+    pub n: usize,
+    pub step: String,
+    pub name: Option<String>,
+    pub content_type: String,
+    pub bytes: usize,
+}
+
+impl JobView {
+    pub fn new(id: &str, job: &JobResult) -> Self {
+        let duration_ms = job.started_at.map(|start| {
+            let end = job.finished_at.unwrap_or_else(Utc::now);
+            (end - start).num_milliseconds().max(0) as u64
+        });
+        Self {
+            job_id: id.to_string(),
+            status: job.status,
+            result: job.output.clone(),
+            created_at: job.created_at,
+            started_at: job.started_at,
+            finished_at: job.finished_at,
+            duration_ms,
+            tags: job.tags.clone(),
+            agent: job.agent.clone(),
+            current_step: job.current_step.clone(),
+            steps: job.steps.clone(),
+            vars: job.vars.clone(),
+            failure: job.failure.clone(),
+            artifacts: job
+                .artifacts
+                .iter()
+                .enumerate()
+                .map(|(n, a)| ArtifactInfo {
+                    n,
+                    step: a.step.clone(),
+                    name: a.name.clone(),
+                    content_type: a.content_type.clone(),
+                    bytes: a.data.len(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Labels a job is submitted with, for the `GET /jobs` filters.
+#[derive(Clone, Debug, Default)]
+pub struct JobOrigin {
+    pub tags: Vec<String>,
+    /// fingerprint of the submitter's agent token
+    pub agent: Option<String>,
+}
+
+/// Query of `GET /jobs`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct JobFilter {
+    pub status: Option<JobStatus>,
+    /// created at or after
+    pub since: Option<DateTime<Utc>>,
+    /// created before
+    pub until: Option<DateTime<Utc>>,
+    pub tag: Option<String>,
+    /// agent fingerprint, as shown in `agent` of a job
+    pub agent: Option<String>,
+    pub offset: usize,
+    /// page size, at most `MAX_PAGE`
+    pub limit: Option<usize>,
+}
+
+impl JobFilter {
+    fn matches(&self, job: &JobResult) -> bool {
+        self.status.is_none_or(|s| job.status == s)
+            && self.since.is_none_or(|t| job.created_at >= t)
+            && self.until.is_none_or(|t| job.created_at < t)
+            && self.tag.as_ref().is_none_or(|t| job.tags.contains(t))
+            && self
+                .agent
+                .as_ref()
+                .is_none_or(|a| job.agent.as_ref() == Some(a))
+    }
+}
+
+/// What `GET /jobs` returns: newest jobs first.
+#[derive(Serialize)]
+pub struct JobPage {
+    /// matching jobs across all pages
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    pub jobs: Vec<JobView>,
+}
+
+/// Finished jobs that the retention limits say to forget: finished longer
+/// than `max_age` ago, or not among the newest `max_count` jobs. Queued and
+/// running jobs are always kept.
+fn expired(
+    jobs: &HashMap<String, JobResult>,
+    max_age: Option<chrono::Duration>,
+    max_count: Option<usize>,
+    now: DateTime<Utc>,
+) -> Vec<String> {
+    let mut newest_first: Vec<_> = jobs.iter().collect();
+    newest_first.sort_by_key(|(_, job)| Reverse(job.created_at));
+    newest_first
+        .into_iter()
+        .enumerate()
+        .filter(|(rank, (_, job))| {
+            let finished = !matches!(job.status, JobStatus::Pending | JobStatus::Running);
+            let age = now - job.finished_at.unwrap_or(job.created_at);
+            finished
+                && (max_age.is_some_and(|max| age > max)
+                    || max_count.is_some_and(|max| *rank >= max))
+        })
+        .map(|(_, (id, _))| id.clone())
+        .collect()
+}
+
+/// Retention state shared by submissions and the prune task.
+#[derive(Default)]
+struct Retention {
+    /// limits from the policy, reloaded on every prune tick
+    max_age: Option<chrono::Duration>,
+    max_count: Option<usize>,
+    /// ids gone from the map but still in the store, until the next `prune`
+    evicted: Vec<String>,
+}
+
+type SharedRetention = Arc<std::sync::Mutex<Retention>>;
+
+/// Evict expired jobs from the map and return their ids.
+async fn evict(map: &JobMap, retention: &SharedRetention) -> Vec<String> {
+    let (max_age, max_count) = {
+        let r = retention.lock().unwrap();
+        (r.max_age, r.max_count)
+    };
+    let mut guard = map.write().await;
+    let ids = expired(&guard, max_age, max_count, Utc::now());
+    for id in &ids {
+        guard.remove(id);
+    }
+    ids
+}
+
+/// Reload the retention limits, evict expired jobs from the map, then drop
+/// them from the store together with those already evicted by submissions,
+/// in one compaction. Ids the store failed to drop are retried next time.
+async fn prune(map: &JobMap, store: &dyn JobStore, retention: &SharedRetention) {
+    match policy::load() {
+        Ok(pol) => {
+            let mut r = retention.lock().unwrap();
+            r.max_age = pol
+                .max_job_age_hours
+                .map(|h| chrono::Duration::hours(h as i64));
+            r.max_count = pol.max_jobs;
+        }
+        Err(e) => eprintln!("[store] keeping the previous retention limits: {:#}", e),
+    }
+    let mut ids = evict(map, retention).await;
+    ids.append(&mut retention.lock().unwrap().evicted);
+    if ids.is_empty() {
+        return;
+    }
+    if let Err(e) = store.remove(&ids) {
+        eprintln!("[store] evicting {} job(s) failed: {:#}", ids.len(), e);
+        retention.lock().unwrap().evicted.extend(ids);
+    }
+}
+
+/// Evidence captured by a `screenshot` / `snapshot` step, served at
+/// `GET /job/{id}/artifacts/{n}`. Stores keep `data` next to the job.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Artifact {
+    pub step: String,
+    pub name: Option<String>,
+    pub content_type: String,
+    #[serde(skip)]
+    pub data: Arc<Vec<u8>>,
+}
+
+impl Artifact {
+    /// File extension for `content_type`.
+    pub fn extension(&self) -> &'static str {
+        if self.content_type == "image/png" {
+            "png"
+        } else {
+            "json"
+        }
+    }
+}
+
+/// Per-run state shared by the steps of one job.
+pub type EventSink = Box<dyn FnMut(&JobEvent) + Send>;
+
+#[derive(Default)]
+pub struct JobContext {
+    pub vars: BTreeMap<String, String>,
+    /// one record per executed step, in execution order
+    pub steps: Vec<StepRecord>,
+    /// list-level `defaults` for timeouts and retries
+    pub defaults: StepOptions,
+    /// id of the step currently running
+    pub step: String,
+    pub artifacts: Vec<Artifact>,
+    /// when set, every executed step is appended to the audit log under this id
+    pub job_id: Option<String>,
+    /// checked between steps, between retries and while waiting
+    pub cancel: Arc<AtomicBool>,
+    /// end of the running step's `timeout_ms`, set by `run_with_retries`
+    pub deadline: Option<Instant>,
+    /// called as steps start and finish and artifacts are captured
+    pub on_event: Option<EventSink>,
+}
+
+impl JobContext {
+    fn emit(&mut self, event: JobEvent) {
+        if let Some(on_event) = &mut self.on_event {
+            on_event(&event);
+        }
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancel.load(Ordering::SeqCst)
+    }
+
+    /// Fails once the job is cancelled or the step's `timeout_ms` is up.
+    fn interrupt(&self) -> anyhow::Result<()> {
+        if self.cancelled() {
+            return Err(anyhow!("job cancelled"));
+        }
+        if self.deadline.is_some_and(|d| Instant::now() >= d) {
+            return Err(anyhow!("step timed out (timeout_ms)"));
+        }
+        Ok(())
+    }
+
+    /// `ui.wait_ms` in slices, failing early if `interrupt` does.
+    fn sleep(&self, ui: &dyn UiAdapter, ms: u64) -> anyhow::Result<()> {
+        let end = Instant::now() + Duration::from_millis(ms);
+        loop {
+            self.interrupt()?;
+            let left = end.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Ok(());
+            }
+            ui.wait_ms((left.as_millis() as u64).min(POLL_INTERVAL_MS));
+        }
+    }
+
+    fn attach(&mut self, name: &Option<String>, content_type: &'static str, data: Vec<u8>) {
+        let artifact = Artifact {
+            step: self.step.clone(),
+            name: name.clone(),
+            content_type: content_type.to_string(),
+            data: Arc::new(data),
+        };
+        self.emit(JobEvent::Artifact {
+            n: self.artifacts.len(),
+            bytes: artifact.data.len(),
+            artifact: artifact.clone(),
+        });
+        self.artifacts.push(artifact);
+    }
+
+    /// Replace `{var.name}` with captured values; unknown names are an error.
+    fn expand_vars(&self, text: &str) -> anyhow::Result<String> {
+        let mut missing = None;
+        let out = VAR_REGEX.replace_all(text, |caps: &regex::Captures| {
+            match self.vars.get(&caps[1]) {
+                Some(v) => v.clone(),
+                None => {
+                    missing.get_or_insert_with(|| caps[1].to_string());
+                    String::new()
+                }
+            }
+        });
+        match missing {
+            Some(name) => Err(anyhow!("variable `{}` is not set", name)),
+            None => Ok(out.into_owned()),
+        }
+    }
 }
 
+type JobMap = Arc<RwLock<HashMap<String, JobResult>>>;
+/// event senders of the jobs that have not finished yet
+type Channels = Arc<std::sync::Mutex<HashMap<String, broadcast::Sender<JobEvent>>>>;
+
 pub struct JobManager {
-    map: Arc<RwLock<HashMap<String, JobResult>>>,
+    map: JobMap,
+    store: Arc<dyn JobStore>,
+    channels: Channels,
+    retention: SharedRetention,
+    /// set by `halt`: queued jobs are cancelled and new ones refused
+    halted: AtomicBool,
     sender: mpsc::Sender<(String, JobRequest)>,
     sender_json: mpsc::Sender<(String, ActionList)>,
 }
@@ -44,20 +573,135 @@ pub enum JobRequest {
     New(ActionList),
 }
 
+/// Record `job` in the store; a failed write is logged, never fatal.
+fn persist(store: &dyn JobStore, id: &str, job: &JobResult) {
+    if let Err(e) = store.save(id, job) {
+        eprintln!("[store] saving job {} failed: {:#}", id, e);
+    }
+}
+
+/// Record a finished step of job `id`; like `persist`, never fatal.
+fn persist_step(store: &dyn JobStore, id: &str, step: &StepRecord) {
+    if let Err(e) = store.save_step(id, step) {
+        eprintln!("[store] saving a step of job {} failed: {:#}", id, e);
+    }
+}
+
+/// Record artifact `n` of job `id`; like `persist`, never fatal.
+fn persist_artifact(store: &dyn JobStore, id: &str, n: usize, artifact: &Artifact) {
+    if let Err(e) = store.save_artifact(id, n, artifact) {
+        eprintln!(
+            "[store] saving artifact {} of job {} failed: {:#}",
+            n, id, e
+        );
+    }
+}
+
+/// Send `event` to everyone watching job `id`.
+fn publish(channels: &Channels, id: &str, event: JobEvent) {
+    if let Some(tx) = channels.lock().unwrap().get(id) {
+        // nobody listening is fine
+        let _ = tx.send(event);
+    }
+}
+
+/// Stamp a job that reached its final status, save it and send the last
+/// event to its watchers.
+fn finish(store: &dyn JobStore, channels: &Channels, id: &str, job: &mut JobResult) {
+    job.finished_at = Some(Utc::now());
+    job.current_step = None;
+    persist(store, id, job);
+    publish(
+        channels,
+        id,
+        JobEvent::Finished(Box::new(JobView::new(id, job))),
+    );
+    channels.lock().unwrap().remove(id);
+}
+
+/// Fail a job whose worker thread panicked, along with the step it was on.
+fn fail_panicked(job: &mut JobResult, err: tokio::task::JoinError) {
+    let reason = match err.try_into_panic() {
+        Ok(payload) => payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".into()),
+        Err(err) => err.to_string(),
+    };
+    let message = mask_text(format!("job worker panicked: {}", reason));
+    if let Some(step) = job.steps.last_mut() {
+        if step.status == JobStatus::Running {
+            step.status = JobStatus::Failed;
+            step.error = Some(message.clone());
+        }
+    }
+    job.status = JobStatus::Failed;
+    job.output = Some(message);
+}
+
+/// Mark a dequeued job `Running` and hand out its cancel flag, or `None` if
+/// it was cancelled while still queued.
+async fn start_job(map: &JobMap, store: &dyn JobStore, id: &str) -> Option<Arc<AtomicBool>> {
+    let mut guard = map.write().await;
+    let entry = guard.get_mut(id)?;
+    if entry.status == JobStatus::Cancelled {
+        return None;
+    }
+    entry.status = JobStatus::Running;
+    entry.started_at = Some(Utc::now());
+    persist(store, id, entry);
+    Some(entry.cancel.clone())
+}
+
 impl JobManager {
+    /// Jobs are kept in `~/.thin-sag/jobs`, or only in memory if that
+    /// directory can't be created.
     pub fn new() -> Self {
+        let store: Arc<dyn JobStore> = match JsonlStore::open_default() {
+            Ok(store) => Arc::new(store),
+            Err(e) => {
+                eprintln!("[store] {:#}; jobs will not survive a restart", e);
+                Arc::new(MemoryStore)
+            }
+        };
+        Self::with_store(store)
+    }
+
+    pub fn with_store(store: Arc<dyn JobStore>) -> Self {
         let (tx, mut rx) = mpsc::channel::<(String, JobRequest)>(100);
         let (tx_json, mut rx_json) = mpsc::channel::<(String, ActionList)>(100);
-        let map: Arc<RwLock<HashMap<String, JobResult>>> = Arc::new(RwLock::new(HashMap::new()));
+        let loaded = store.load().unwrap_or_else(|e| {
+            eprintln!("[store] loading jobs failed: {:#}", e);
+            vec![]
+        });
+        let map: JobMap = Arc::new(RwLock::new(loaded.into_iter().collect()));
         let map_clone_1 = map.clone();
         let map_clone_2 = map.clone();
+        let store_1 = store.clone();
+        let store_2 = store.clone();
+        let channels = Channels::default();
+        let channels_1 = channels.clone();
+        let channels_2 = channels.clone();
+        let map_clone_3 = map.clone();
+        let store_3 = store.clone();
+        let retention = SharedRetention::default();
+        let retention_3 = retention.clone();
+
+        // the first tick is immediate, which also trims what was just loaded
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(PRUNE_INTERVAL);
+            loop {
+                ticks.tick().await;
+                prune(&map_clone_3, &*store_3, &retention_3).await;
+            }
+        });
 
         tokio::spawn(async move {
             while let Some((id, req)) = rx.recv().await {
-                {
-                    let mut guard = map_clone_1.write().await;
-                    guard.get_mut(&id).unwrap().status = JobStatus::Running;
-                }
+                let Some(cancel) = start_job(&map_clone_1, &*store_1, &id).await else {
+                    continue;
+                };
                 let res = (|| -> Result<RunResponse, ApiError> {
                     if let JobRequest::Old(req) = req {
                         let secret = vault::get_secret(&req.secret).map_err(ApiError::Internal)?;
@@ -65,8 +709,11 @@ impl JobManager {
                         MacAdapter::new()
                             .launch(&req.bundle)
                             .map_err(ApiError::Internal)?;
+                        if cancel.load(Ordering::SeqCst) {
+                            return Err(ApiError::Conflict(anyhow!("job cancelled")));
+                        }
                         MacAdapter::new()
-                            .type_text(&text)
+                            .type_text(&text, TypeMode::default(), Layout::default())
                             .map_err(ApiError::Internal)?;
                         Ok(RunResponse::success())
                     } else {
@@ -81,118 +728,1299 @@ impl JobManager {
                 match res {
                     Ok(res) => {
                         entry.status = JobStatus::Success;
-                        entry.output = Some(format!("{:?}", res));
+                        entry.output = res.message.or(Some("Login sequence executed".to_string()));
                     }
                     Err(e) => {
-                        entry.status = JobStatus::Failed;
-                        entry.output = Some(format!("{:?}", RunResponse::fail(e.to_string())));
+                        entry.status = if cancel.load(Ordering::SeqCst) {
+                            JobStatus::Cancelled
+                        } else {
+                            JobStatus::Failed
+                        };
+                        entry.output = RunResponse::fail(e.to_string()).message;
                     }
                 }
+                finish(&*store_1, &channels_1, &id, entry);
             }
         });
 
         tokio::spawn(async move {
             while let Some((id, actions)) = rx_json.recv().await {
-                {
-                    let mut guard = map_clone_2.write().await;
-                    guard.get_mut(&id).unwrap().status = JobStatus::Running;
-                }
+                let Some(cancel) = start_job(&map_clone_2, &*store_2, &id).await else {
+                    continue;
+                };
 
-                let mut expanded_actions = Vec::with_capacity(actions.0.len());
-                for act in actions.0 {
-                    let act = match act {
-                        Action::Type { text } => {
-                            let processed_text = SECRET_REGEX
-                                .replace_all(&text, |caps: &regex::Captures| {
-                                    let label = &caps[1];
-                                    vault::get_secret(label).unwrap_or_else(|_| "".into())
-                                })
-                                .to_string();
-                            Action::Type {
-                                text: processed_text,
+                let live = map_clone_2.clone();
+                let live_store = store_2.clone();
+                let live_id = id.clone();
+                let live_channels = channels_2.clone();
+                let mut ctx = JobContext {
+                    job_id: Some(id.clone()),
+                    cancel,
+                    // runs on the blocking thread below, so it may block on the map
+                    on_event: Some(Box::new(move |event| {
+                        let mut guard = live.blocking_write();
+                        if let Some(entry) = guard.get_mut(&live_id) {
+                            entry.apply(event);
+                            match event {
+                                JobEvent::StepFinished(record) => {
+                                    persist_step(&*live_store, &live_id, record)
+                                }
+                                JobEvent::Artifact { n, artifact, .. } => {
+                                    persist_artifact(&*live_store, &live_id, *n, artifact)
+                                }
+                                _ => {}
                             }
+                            // under the map lock, so `watch` sees each event once
+                            publish(&live_channels, &live_id, event.clone());
                         }
-                        other => other,
-                    };
-                    expanded_actions.push(act);
-                }
-
-                let res = execute_actions(&expanded_actions);
+                    })),
+                    ..JobContext::default()
+                };
+                let joined = tokio::task::spawn_blocking(move || {
+                    let res = run_actions(actions, &mut ctx);
+                    (res, ctx)
+                })
+                .await;
 
                 let mut guard = map_clone_2.write().await;
                 let entry = guard.get_mut(&id).unwrap();
+                let (res, ctx) = match joined {
+                    Ok(done) => done,
+                    // keep serving the queue; the events recorded what ran
+                    Err(e) => {
+                        fail_panicked(entry, e);
+                        finish(&*store_2, &channels_2, &id, entry);
+                        continue;
+                    }
+                };
+                let cancelled = ctx.cancelled();
+                entry.vars = ctx.vars;
+                entry.steps = ctx.steps;
+                entry.current_step = None;
+                entry.artifacts = ctx.artifacts;
                 match res {
                     Ok(()) => {
                         entry.status = JobStatus::Success;
                         entry.output = Some("Actions executed successfully".to_string());
                     }
+                    Err(e) if cancelled => {
+                        entry.status = JobStatus::Cancelled;
+                        entry.output = Some(format!("Cancelled: {}", e));
+                        entry.failure = Some(*e);
+                    }
                     Err(e) => {
                         entry.status = JobStatus::Failed;
                         entry.output = Some(format!("Error executing actions: {}", e));
+                        entry.failure = Some(*e);
                     }
                 }
+                finish(&*store_2, &channels_2, &id, entry);
             }
         });
 
         Self {
             map,
+            store,
+            channels,
+            retention,
+            halted: AtomicBool::new(false),
             sender: tx,
             sender_json: tx_json,
         }
     }
 
-    pub async fn enqueue(&self, req: RunRequest) -> String {
-        let id = Uuid::new_v4().to_string();
-        self.map.write().await.insert(
-            id.clone(),
-            JobResult {
-                status: JobStatus::Pending,
-                output: None,
-            },
-        );
+    pub async fn enqueue(&self, req: RunRequest, origin: JobOrigin) -> Result<String, ApiError> {
+        let id = self.insert_pending(origin).await?;
         self.sender
             .send((id.clone(), JobRequest::Old(req)))
             .await
             .unwrap();
-        id
+        Ok(id)
     }
 
-    pub async fn enqueue_json(&self, actions: ActionList) -> String {
-        let id = Uuid::new_v4().to_string();
-        self.map.write().await.insert(
-            id.clone(),
-            JobResult {
-                status: JobStatus::Pending,
-                output: None,
-            },
-        );
+    pub async fn enqueue_json(
+        &self,
+        actions: ActionList,
+        origin: JobOrigin,
+    ) -> Result<String, ApiError> {
+        let id = self.insert_pending(origin).await?;
         self.sender_json.send((id.clone(), actions)).await.unwrap();
-        id
+        Ok(id)
+    }
+
+    async fn insert_pending(&self, origin: JobOrigin) -> Result<String, ApiError> {
+        if self.is_halted() {
+            return Err(ApiError::Conflict(anyhow!(
+                "halted: no new jobs until POST /resume"
+            )));
+        }
+        // the store catches up at the next prune tick
+        let ids = evict(&self.map, &self.retention).await;
+        self.retention.lock().unwrap().evicted.extend(ids);
+        let id = Uuid::new_v4().to_string();
+        let job = JobResult {
+            tags: origin.tags,
+            agent: origin.agent,
+            ..JobResult::pending()
+        };
+        persist(&*self.store, &id, &job);
+        self.channels
+            .lock()
+            .unwrap()
+            .insert(id.clone(), broadcast::channel(EVENT_BUFFER).0);
+        self.map.write().await.insert(id.clone(), job);
+        Ok(id)
     }
 
     pub async fn get(&self, id: &str) -> Option<JobResult> {
         self.map.read().await.get(id).cloned()
     }
+
+    /// One page of the jobs matching `filter`, newest first.
+    pub async fn list(&self, filter: &JobFilter) -> JobPage {
+        let guard = self.map.read().await;
+        let mut jobs: Vec<_> = guard.iter().filter(|(_, j)| filter.matches(j)).collect();
+        jobs.sort_by_key(|(_, job)| Reverse(job.created_at));
+        let limit = filter.limit.unwrap_or(DEFAULT_PAGE).min(MAX_PAGE);
+        JobPage {
+            total: jobs.len(),
+            offset: filter.offset,
+            limit,
+            jobs: jobs
+                .into_iter()
+                .skip(filter.offset)
+                .take(limit)
+                .map(|(id, job)| JobView::new(id, job))
+                .collect(),
+        }
+    }
+
+    /// The job as it is now plus a receiver for its next events, or no
+    /// receiver once it has finished. Both are taken under the map lock, so
+    /// no event falls between them.
+    pub async fn watch(
+        &self,
+        id: &str,
+    ) -> Option<(JobView, Option<broadcast::Receiver<JobEvent>>)> {
+        let guard = self.map.read().await;
+        let job = guard.get(id)?;
+        let rx = self
+            .channels
+            .lock()
+            .unwrap()
+            .get(id)
+            .map(broadcast::Sender::subscribe);
+        Some((JobView::new(id, job), rx))
+    }
+
+    /// Cancel a job. A queued job is `Cancelled` at once; a running one
+    /// stops at its next check (between steps, retries and wait slices) and
+    /// keeps `Running` until then. Returns the job's status afterwards.
+    pub async fn cancel(&self, id: &str) -> Result<JobStatus, ApiError> {
+        let mut guard = self.map.write().await;
+        let entry = guard
+            .get_mut(id)
+            .ok_or_else(|| ApiError::NotFound(anyhow!("Job ID 不明")))?;
+        match entry.status {
+            JobStatus::Pending => {
+                entry.status = JobStatus::Cancelled;
+                entry.output = Some("Cancelled before it started".to_string());
+                finish(&*self.store, &self.channels, id, entry);
+            }
+            JobStatus::Running => entry.cancel.store(true, Ordering::SeqCst),
+            status => {
+                return Err(ApiError::Conflict(anyhow!(
+                    "job already finished ({:?})",
+                    status
+                )))
+            }
+        }
+        Ok(entry.status)
+    }
+
+    /// Emergency stop: refuse new jobs and cancel every queued or running
+    /// one. Returns the ids that were cancelled.
+    pub async fn halt(&self) -> Vec<String> {
+        self.halted.store(true, Ordering::SeqCst);
+        let active: Vec<String> = self
+            .map
+            .read()
+            .await
+            .iter()
+            .filter(|(_, job)| matches!(job.status, JobStatus::Pending | JobStatus::Running))
+            .map(|(id, _)| id.clone())
+            .collect();
+        let mut cancelled = Vec::new();
+        for id in active {
+            // a job may finish between the scan and here
+            if self.cancel(&id).await.is_ok() {
+                cancelled.push(id);
+            }
+        }
+        cancelled
+    }
+
+    /// Accept jobs again after `halt`.
+    pub fn resume(&self) {
+        self.halted.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted.load(Ordering::SeqCst)
+    }
+}
+
+fn expand_secret_refs(text: &str) -> String {
+    SECRET_REGEX
+        .replace_all(text, |caps: &regex::Captures| {
+            let label = &caps[1];
+            vault::get_secret(label).unwrap_or_else(|_| "".into())
+        })
+        .to_string()
+}
+
+/// Replace `{secret.label}` in every `type` / `clipboard_set` / `set_value` text,
+/// including nested blocks.
+fn expand_secrets(steps: Vec<Step>) -> Vec<Step> {
+    steps
+        .into_iter()
+        .map(|Step { meta, act, options }| Step {
+            meta,
+            act: expand_step_secrets(act),
+            options,
+        })
+        .collect()
+}
+
+fn expand_step_secrets(act: Action) -> Action {
+    match act {
+        Action::Type {
+            text,
+            mode,
+            layout,
+            window,
+        } => Action::Type {
+            text: expand_secret_refs(&text),
+            mode,
+            layout,
+            window,
+        },
+        Action::ClipboardSet { text } => Action::ClipboardSet {
+            text: expand_secret_refs(&text),
+        },
+        Action::SetValue {
+            selector,
+            value,
+            window,
+        } => Action::SetValue {
+            selector,
+            value: expand_secret_refs(&value),
+            window,
+        },
+        Action::If {
+            cond,
+            then,
+            otherwise,
+        } => Action::If {
+            cond,
+            then: expand_secrets(then),
+            otherwise: expand_secrets(otherwise),
+        },
+        Action::Repeat { times, cond, body } => Action::Repeat {
+            times,
+            cond,
+            body: expand_secrets(body),
+        },
+        other => other,
+    }
 }
 
-fn execute_actions(actions: &[Action]) -> Result<(), ApiError> {
+/// Expand macros and check the list against the policy; shared by
+/// `/run-json` and `thin-sag exec`.
+pub fn prepare_actions(list: ActionList) -> anyhow::Result<ActionList> {
+    let list = expand_macros(list)?;
+    policy::validate_actions(&list)?;
+    Ok(list)
+}
+
+/// Expand secrets and run a prepared list on the Mac.
+pub fn run_actions(list: ActionList, ctx: &mut JobContext) -> Result<(), Box<StepFailure>> {
+    ctx.defaults = list.defaults;
+    let actions = expand_secrets(list.actions);
     let ui = MacAdapter::new();
+    run_list(&ui, &actions, ctx, "")
+}
 
-    for act in actions {
+/// Runs `actions` in order. Steps are numbered by their position, with
+/// nested block steps as `parent.child` (e.g. `3.1`).
+fn run_list(
+    ui: &dyn UiAdapter,
+    actions: &[Step],
+    ctx: &mut JobContext,
+    prefix: &str,
+) -> Result<(), Box<StepFailure>> {
+    for (i, Step { meta, act, options }) in actions.iter().enumerate() {
+        let step = step_id(prefix, i);
+        let fail = |e| StepFailure::new(&step, meta, act, e);
+        if ctx.cancelled() {
+            return Err(Box::new(fail(anyhow!("job cancelled"))));
+        }
         match act {
-            Action::Launch { target } => ui.launch(target)?,
-            Action::Type { text } => {
-                register_secret(text);
-                ui.type_text(text)?;
+            Action::If {
+                cond,
+                then,
+                otherwise,
+            } => {
+                if condition_holds(cond, ctx).map_err(fail)? {
+                    run_list(ui, then, ctx, &step)?;
+                } else {
+                    run_list(ui, otherwise, ctx, &step)?;
+                }
+            }
+            Action::Repeat { times, cond, body } => {
+                for _ in 0..*times {
+                    if let Some(cond) = cond {
+                        if !condition_holds(cond, ctx).map_err(fail)? {
+                            break;
+                        }
+                    }
+                    run_list(ui, body, ctx, &step)?;
+                }
+            }
+            _ => {
+                let opts = options.or(ctx.defaults);
+                ctx.step = step.clone();
+                let mut record = StepRecord {
+                    step: step.clone(),
+                    act: act_name(act).to_string(),
+                    meta: meta.clone(),
+                    status: JobStatus::Running,
+                    started_at: Utc::now(),
+                    duration_ms: 0,
+                    attempts: 0,
+                    error: None,
+                };
+                ctx.emit(JobEvent::StepStarted(record.clone()));
+                let started = Instant::now();
+                let (res, attempts) = run_with_retries(ui, act, opts, ctx);
+                record.status = match &res {
+                    Ok(()) => JobStatus::Success,
+                    Err(_) if ctx.cancelled() => JobStatus::Cancelled,
+                    Err(_) => JobStatus::Failed,
+                };
+                record.duration_ms = started.elapsed().as_millis() as u64;
+                record.attempts = attempts;
+                record.error = res.as_ref().err().map(|e| mask_text(e.to_string()));
+                if let Some(job_id) = &ctx.job_id {
+                    audit_step(job_id, &record);
+                }
+                ctx.emit(JobEvent::StepFinished(record.clone()));
+                ctx.steps.push(record);
+                res.map_err(fail)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Append an executed step to the audit log; a log that cannot be written
+/// does not stop the job.
+fn audit_step(job_id: &str, record: &StepRecord) {
+    let mut entry = serde_json::to_value(record).unwrap_or_default();
+    entry["ts"] = chrono::Utc::now().timestamp().into();
+    entry["job"] = job_id.into();
+    if let Err(e) = audit::append(&entry) {
+        eprintln!("[audit] could not log step {}: {}", record.step, e);
+    }
+}
+
+/// Runs a leaf step, retrying failures up to `opts.retries` times
+/// unless the next attempt would start after `opts.timeout_ms`.
+fn run_with_retries(
+    ui: &dyn UiAdapter,
+    act: &Action,
+    opts: StepOptions,
+    ctx: &mut JobContext,
+) -> (anyhow::Result<()>, u32) {
+    let started = Instant::now();
+    let delay = opts.retry_delay_ms.unwrap_or(POLL_INTERVAL_MS);
+    ctx.deadline = opts
+        .timeout_ms
+        .map(|ms| started + Duration::from_millis(ms));
+    let mut attempts = 0;
+    let res = loop {
+        attempts += 1;
+        let res = run_step(ui, act, opts, ctx);
+        let out_of_time = opts.timeout_ms.is_some_and(|ms| {
+            started.elapsed() + Duration::from_millis(delay) >= Duration::from_millis(ms)
+        });
+        if res.is_ok() || attempts > opts.retries.unwrap_or(0) || out_of_time {
+            break res;
+        }
+        if ctx.sleep(ui, delay).is_err() {
+            break res;
+        }
+    };
+    ctx.deadline = None;
+    (res, attempts)
+}
+
+/// Runs a single (non-block) action.
+fn run_step(
+    ui: &dyn UiAdapter,
+    act: &Action,
+    opts: StepOptions,
+    ctx: &mut JobContext,
+) -> anyhow::Result<()> {
+    match act {
+        Action::Launch { target } => ui.launch(target)?,
+        Action::OpenUrl { url, browser } => ui.open_url(url, browser.as_deref())?,
+        Action::Type {
+            text,
+            mode,
+            layout,
+            window,
+        } => {
+            let text = ctx.expand_vars(text)?;
+            register_secret(&text);
+            if let Some(window) = window {
+                ui.focus_window(window)?;
+            }
+            ui.type_text(&text, *mode, *layout)?;
+        }
+        Action::Wait { ms } => ctx.sleep(ui, *ms)?,
+        Action::WaitFor { cond } => wait_for(ui, cond, opts.timeout_ms, ctx)?,
+        Action::Read {
+            selector,
+            field,
+            into,
+            window,
+        } => {
+            let value = read_element(selector, *field, window.as_ref())?;
+            ctx.vars.insert(into.clone(), value);
+        }
+        Action::Assert {
+            selector,
+            window,
+            title,
+            equals,
+            matches,
+        } => check_assert(
+            selector.as_deref(),
+            window.as_ref(),
+            title.as_deref(),
+            equals.as_deref(),
+            matches.as_deref(),
+        )?,
+        Action::Screenshot { name } => {
+            let png = ui.screenshot()?;
+            ctx.attach(name, "image/png", png);
+        }
+        Action::Snapshot { window, name } => {
+            policy::take_snapshot_slot()?;
+            let tree = snapshot_tree(window.clone().unwrap_or(WindowSelector::Front))?;
+            ctx.attach(
+                name,
+                "application/json",
+                serde_json::to_vec(&tree.masked())?,
+            );
+        }
+        Action::Click { at, modifiers } => ui.click(at, modifiers)?,
+        Action::DoubleClick { at, modifiers } => ui.double_click(at, modifiers)?,
+        Action::RightClick { at, modifiers } => ui.right_click(at, modifiers)?,
+        Action::MoveTo { at } => ui.move_to(at)?,
+        Action::Drag {
+            from,
+            to,
+            duration_ms,
+            modifiers,
+        } => {
+            let res = ui.drag(from, to, *duration_ms, modifiers, &|| {
+                ctx.interrupt().is_err()
+            });
+            if res.is_err() {
+                // report why the drag was stopped, if it was
+                ctx.interrupt()?;
+            }
+            res?;
+        }
+        Action::Scroll { dx, dy, unit, at } => ui.scroll(at, *dx, *dy, *unit)?,
+        Action::Keypress { key, repeat } => {
+            let chords = parse_keys(key)?;
+            for _ in 0..repeat.unwrap_or(1) {
+                ctx.interrupt()?;
+                for chord in &chords {
+                    ui.keypress(chord)?;
+                }
+            }
+        }
+        Action::ClipboardSet { text } => {
+            let text = ctx.expand_vars(text)?;
+            // masked if it ever comes back through clipboard_get / snapshots
+            register_secret(&text);
+            ui.clipboard_set(&text)?;
+        }
+        Action::ClipboardGet { into } => {
+            let text = mask_text(ui.clipboard_get()?);
+            ctx.vars.insert(into.clone(), text);
+        }
+        Action::Paste => ui.paste()?,
+        Action::Press { selector, window } => {
+            ui.press(selector, window.as_ref().unwrap_or(&WindowSelector::Front))?
+        }
+        Action::SetValue {
+            selector,
+            value,
+            window,
+        } => {
+            let value = ctx.expand_vars(value)?;
+            register_secret(&value);
+            ui.set_value(
+                selector,
+                window.as_ref().unwrap_or(&WindowSelector::Front),
+                &value,
+            )?;
+        }
+        Action::Menu { path } => ui.menu(&menu_path(path))?,
+        Action::FocusWindow { window } => ui.focus_window(window)?,
+        Action::MoveWindow { window, x, y } => ui.move_window(window, *x, *y)?,
+        Action::ResizeWindow {
+            window,
+            width,
+            height,
+        } => ui.resize_window(window, *width, *height)?,
+        Action::Minimize { window } => ui.minimize(window)?,
+        Action::CloseWindow { window } => ui.close_window(window)?,
+        Action::QuitApp { target } => ui.quit_app(target)?,
+        Action::If { .. } | Action::Repeat { .. } => {
+            return Err(anyhow!("block acts are run by run_list"))
+        }
+        Action::Call { name, .. } => return Err(anyhow!("macro `{}` was not expanded", name)),
+        Action::Unsupported => return Err(anyhow!("unsupported act")),
+    }
+    Ok(())
+}
+
+/// Evaluate `cond` against one fresh snapshot, or against `ctx.vars`.
+fn condition_holds(cond: &Condition, ctx: &JobContext) -> anyhow::Result<bool> {
+    let re = cond.value.as_deref().map(Regex::new).transpose()?;
+    if let Some(name) = &cond.var {
+        let value = ctx.vars.get(name).filter(|v| !v.is_empty());
+        return Ok(match cond.state {
+            WaitState::Exists => value.is_some(),
+            WaitState::Gone => value.is_none(),
+            WaitState::ValueMatches => value
+                .zip(re.as_ref())
+                .map(|(v, re)| re.is_match(v))
+                .unwrap_or(false),
+            WaitState::Enabled => false,
+        });
+    }
+    let selector = cond
+        .selector
+        .as_deref()
+        .ok_or_else(|| anyhow!("condition needs `selector` or `var`"))?;
+    let sel = Selector::parse(selector)?;
+    let window = cond.window.clone().unwrap_or(WindowSelector::Front);
+    // a window that is not there counts as an empty tree
+    Ok(match snapshot_tree(window) {
+        Ok(tree) => state_holds(&sel, cond.state, re.as_ref(), &tree),
+        Err(_) => cond.state == WaitState::Gone,
+    })
+}
+
+/// Poll snapshots until `cond` holds.
+/// The timeout is capped by `Policy::max_wait_ms`.
+fn wait_for(
+    ui: &dyn UiAdapter,
+    cond: &Condition,
+    timeout_ms: Option<u64>,
+    ctx: &JobContext,
+) -> anyhow::Result<()> {
+    let mut timeout_ms = timeout_ms.unwrap_or(DEFAULT_WAIT_FOR_MS);
+    if let Some(max) = policy::load()?.max_wait_ms {
+        timeout_ms = timeout_ms.min(max);
+    }
+    let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+    while !condition_holds(cond, ctx)? {
+        if Instant::now() >= deadline {
+            return Err(anyhow!(
+                "wait_for `{}` ({:?}) timed out after {}ms",
+                cond.selector.as_deref().unwrap_or_default(),
+                cond.state,
+                timeout_ms
+            ));
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        ctx.sleep(ui, (left.as_millis() as u64).min(POLL_INTERVAL_MS))?;
+    }
+    Ok(())
+}
+
+/// Snapshot `window` and return the masked value or label of the element
+/// matched by `selector`.
+fn read_element(
+    selector: &str,
+    field: ReadField,
+    window: Option<&WindowSelector>,
+) -> anyhow::Result<String> {
+    let sel = Selector::parse(selector)?;
+    let tree = snapshot_tree(window.cloned().unwrap_or(WindowSelector::Front))?;
+    read_field(sel.find_one(&tree)?, field, selector)
+}
+
+/// `field` of `node`, masked. Snapshot values are masked already; labels
+/// (AXTitle) are not.
+fn read_field(node: &UiNode, field: ReadField, selector: &str) -> anyhow::Result<String> {
+    let text = match field {
+        ReadField::Label => &node.label,
+        ReadField::Value => node
+            .value
+            .as_ref()
+            .ok_or_else(|| anyhow!("selector `{}` matched an element without a value", selector))?,
+    };
+    Ok(mask_text(text))
+}
+
+/// Check an `assert` step against a fresh snapshot of `window`.
+fn check_assert(
+    selector: Option<&str>,
+    window: Option<&WindowSelector>,
+    title: Option<&str>,
+    equals: Option<&str>,
+    matches: Option<&str>,
+) -> anyhow::Result<()> {
+    let tree = snapshot_tree(window.cloned().unwrap_or(WindowSelector::Front))?;
+    if let Some(glob) = title {
+        if !Glob::new(glob)?.compile_matcher().is_match(&tree.label) {
+            return Err(AssertionFailed {
+                expected: format!("window title matching `{}`", glob),
+                actual: format!("`{}`", mask_text(&tree.label)),
+                snapshot: Some(Box::new(tree.trimmed(1))),
             }
-            Action::Wait { ms } => ui.wait_ms(*ms),
-            Action::Click { selector, x, y } => ui.click(selector.as_deref(), *x, *y)?,
-            Action::Scroll { dy } => ui.scroll(*dy)?,
-            Action::Keypress { key } => ui.keypress(key)?,
-            Action::Unsupported => {
-                return Err(ApiError::BadRequest(anyhow::anyhow!("unsupported act")))
+            .into());
+        }
+    }
+    let Some(selector) = selector else {
+        return Ok(());
+    };
+    let sel = Selector::parse(selector)?;
+    let (hits, total) = sel.find_all(&tree);
+    if equals.is_none() && matches.is_none() {
+        if hits.is_empty() {
+            return Err(AssertionFailed {
+                expected: format!("an element matching `{}`", selector),
+                actual: format!("0/{} elements matched", total),
+                snapshot: Some(Box::new(tree.trimmed(SNAPSHOT_TRIM_DEPTH))),
             }
+            .into());
+        }
+        return Ok(());
+    }
+    if hits.len() != 1 {
+        return Err(AssertionFailed {
+            expected: format!("exactly one element matching `{}`", selector),
+            actual: format!("{}/{} elements matched", hits.len(), total),
+            snapshot: Some(Box::new(tree.trimmed(SNAPSHOT_TRIM_DEPTH))),
+        }
+        .into());
+    }
+    let node = hits[0];
+    let actual = node.value.as_deref().unwrap_or_default();
+    let failed = |expected: String| AssertionFailed {
+        expected,
+        actual: format!("`{}`", actual),
+        snapshot: Some(Box::new(node.trimmed(SNAPSHOT_TRIM_DEPTH))),
+    };
+    if let Some(want) = equals {
+        if actual != want {
+            return Err(failed(format!("value `{}`", want)).into());
+        }
+    }
+    if let Some(re) = matches {
+        if !Regex::new(re)?.is_match(actual) {
+            return Err(failed(format!("value matching `{}`", re)).into());
         }
     }
     Ok(())
 }
+
+fn state_holds(sel: &Selector, state: WaitState, re: Option<&Regex>, tree: &UiNode) -> bool {
+    let (hits, _) = sel.find_all(tree);
+    match state {
+        WaitState::Exists => !hits.is_empty(),
+        WaitState::Gone => hits.is_empty(),
+        WaitState::Enabled => hits.iter().any(|n| n.enabled.unwrap_or(false)),
+        WaitState::ValueMatches => hits.iter().any(|n| match (re, n.value.as_deref()) {
+            (Some(re), Some(v)) => re.is_match(v),
+            _ => false,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_vars() {
+        let mut ctx = JobContext::default();
+        ctx.vars.insert("order".into(), "A-1234".into());
+        assert_eq!(
+            ctx.expand_vars("order {var.order} done").unwrap(),
+            "order A-1234 done"
+        );
+        let err = ctx.expand_vars("{var.missing}").unwrap_err();
+        assert!(err.to_string().contains("missing"));
+    }
+
+    #[test]
+    fn test_assert_failure_details() {
+        let act: Action = serde_json::from_str(r#"{"act":"assert","title":"Inbox*"}"#).unwrap();
+        let err = anyhow::Error::from(AssertionFailed {
+            expected: "window title matching `Inbox*`".into(),
+            actual: "`Drafts`".into(),
+            snapshot: None,
+        });
+        let failure = StepFailure::new("2.1", &StepMeta::default(), &act, err);
+        assert_eq!(failure.act, "assert");
+        assert_eq!(failure.actual.as_deref(), Some("`Drafts`"));
+        assert!(failure
+            .to_string()
+            .starts_with("step 2.1 (assert): assertion failed"));
+
+        register_secret("s3cret-draft");
+        let tree = crate::tree::node("AXWindow", "s3cret-draft", None, vec![]);
+        let err = anyhow::Error::from(AssertionFailed {
+            expected: "window title matching `Inbox*`".into(),
+            actual: format!("`{}`", mask_text(&tree.label)),
+            snapshot: Some(Box::new(tree)),
+        });
+        let failure = StepFailure::new("2.1", &StepMeta::default(), &act, err);
+        assert_eq!(failure.actual.as_deref(), Some("`***MASK***`"));
+        assert_eq!(failure.snapshot.unwrap().label, "***MASK***");
+    }
+
+    #[tokio::test]
+    async fn test_panicked_worker_fails_the_job() {
+        let mut job = JobResult::pending();
+        job.apply(&JobEvent::StepStarted(StepRecord {
+            step: "0".into(),
+            act: "click".into(),
+            meta: StepMeta::default(),
+            status: JobStatus::Running,
+            started_at: Utc::now(),
+            duration_ms: 0,
+            attempts: 0,
+            error: None,
+        }));
+        let err = tokio::task::spawn_blocking(|| panic!("AX element vanished"))
+            .await
+            .unwrap_err();
+        fail_panicked(&mut job, err);
+        assert_eq!(job.status, JobStatus::Failed);
+        assert_eq!(
+            job.output.as_deref(),
+            Some("job worker panicked: AX element vanished")
+        );
+        assert_eq!(job.steps[0].status, JobStatus::Failed);
+    }
+
+    /// Store whose evictions always fail.
+    struct StuckStore;
+
+    impl JobStore for StuckStore {
+        fn load(&self) -> anyhow::Result<Vec<(String, JobResult)>> {
+            Ok(vec![])
+        }
+
+        fn save(&self, _: &str, _: &JobResult) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn save_step(&self, _: &str, _: &StepRecord) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn save_artifact(&self, _: &str, _: usize, _: &Artifact) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn remove(&self, _: &[String]) -> anyhow::Result<()> {
+            Err(anyhow!("disk full"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_evictions_are_retried() {
+        let old = JobResult {
+            status: JobStatus::Success,
+            finished_at: Some(Utc::now() - chrono::Duration::days(365)),
+            ..JobResult::pending()
+        };
+        let map: JobMap = Arc::new(RwLock::new([("old".to_string(), old)].into()));
+        let retention = SharedRetention::default();
+        prune(&map, &StuckStore, &retention).await;
+        assert!(map.read().await.is_empty());
+        assert_eq!(retention.lock().unwrap().evicted, ["old"]);
+    }
+
+    #[test]
+    fn test_var_condition() {
+        let mut ctx = JobContext::default();
+        ctx.vars.insert("order".into(), "A-1234".into());
+        let cond: Condition =
+            serde_json::from_str(r#"{"var":"order","state":"value_matches","value":"^A-\\d+$"}"#)
+                .unwrap();
+        assert!(condition_holds(&cond, &ctx).unwrap());
+        let cond: Condition = serde_json::from_str(r#"{"var":"other","state":"gone"}"#).unwrap();
+        assert!(condition_holds(&cond, &ctx).unwrap());
+    }
+
+    /// Adapter that logs calls instead of driving the UI.
+    #[derive(Default)]
+    struct FakeUi(std::sync::Mutex<Vec<String>>);
+
+    impl FakeUi {
+        fn log(&self, call: &str) -> anyhow::Result<()> {
+            self.0.lock().unwrap().push(call.to_string());
+            Ok(())
+        }
+    }
+
+    impl UiAdapter for FakeUi {
+        fn launch(&self, target: &str) -> anyhow::Result<()> {
+            self.log(&format!("launch {}", target))
+        }
+        fn open_url(&self, url: &str, _: Option<&str>) -> anyhow::Result<()> {
+            self.log(&format!("open_url {}", url))
+        }
+        fn click(
+            &self,
+            _: &crate::action::Point,
+            _: &[crate::action::Modifier],
+        ) -> anyhow::Result<()> {
+            self.log("click")
+        }
+        fn double_click(
+            &self,
+            _: &crate::action::Point,
+            _: &[crate::action::Modifier],
+        ) -> anyhow::Result<()> {
+            self.log("double_click")
+        }
+        fn right_click(
+            &self,
+            _: &crate::action::Point,
+            _: &[crate::action::Modifier],
+        ) -> anyhow::Result<()> {
+            self.log("right_click")
+        }
+        fn move_to(&self, _: &crate::action::Point) -> anyhow::Result<()> {
+            self.log("move_to")
+        }
+        fn drag(
+            &self,
+            _: &crate::action::Point,
+            _: &crate::action::Point,
+            _: u64,
+            _: &[crate::action::Modifier],
+            _: &dyn Fn() -> bool,
+        ) -> anyhow::Result<()> {
+            self.log("drag")
+        }
+        fn type_text(&self, text: &str, _: TypeMode, _: Layout) -> anyhow::Result<()> {
+            self.log(&format!("type {}", text))
+        }
+        fn scroll(
+            &self,
+            _: &crate::action::Point,
+            _: i32,
+            _: i32,
+            _: crate::action::ScrollUnit,
+        ) -> anyhow::Result<()> {
+            self.log("scroll")
+        }
+        fn keypress(&self, chord: &crate::keymap::Chord) -> anyhow::Result<()> {
+            self.log(&format!("keypress {:?} {:#x}", chord.modifiers, chord.code))?;
+            // F13 fails on the first two calls, like a transient CGEvent error
+            if chord.code == 0x69 && self.0.lock().unwrap().len() <= 2 {
+                return Err(anyhow!("CGEventSource::new failed"));
+            }
+            Ok(())
+        }
+        fn clipboard_set(&self, _: &str) -> anyhow::Result<()> {
+            self.log("clipboard_set")
+        }
+        fn clipboard_get(&self) -> anyhow::Result<String> {
+            Ok(String::new())
+        }
+        fn paste(&self) -> anyhow::Result<()> {
+            self.log("paste")
+        }
+        fn press(&self, selector: &str, _: &WindowSelector) -> anyhow::Result<()> {
+            self.log(&format!("press {}", selector))
+        }
+        fn set_value(&self, _: &str, _: &WindowSelector, value: &str) -> anyhow::Result<()> {
+            self.log(&format!("set_value {}", value))
+        }
+        fn menu(&self, path: &[&str]) -> anyhow::Result<()> {
+            self.log(&format!("menu {}", path.join("|")))
+        }
+        fn focus_window(&self, window: &WindowSelector) -> anyhow::Result<()> {
+            self.log(&format!("focus {:?}", window))
+        }
+        fn move_window(&self, _: &WindowSelector, x: i32, y: i32) -> anyhow::Result<()> {
+            self.log(&format!("move_window {},{}", x, y))
+        }
+        fn resize_window(&self, _: &WindowSelector, w: u32, h: u32) -> anyhow::Result<()> {
+            self.log(&format!("resize_window {}x{}", w, h))
+        }
+        fn minimize(&self, _: &WindowSelector) -> anyhow::Result<()> {
+            self.log("minimize")
+        }
+        fn close_window(&self, _: &WindowSelector) -> anyhow::Result<()> {
+            self.log("close_window")
+        }
+        fn quit_app(&self, target: &str) -> anyhow::Result<()> {
+            self.log(&format!("quit_app {}", target))
+        }
+        fn screenshot(&self) -> anyhow::Result<Vec<u8>> {
+            Ok(b"\x89PNG".to_vec())
+        }
+        // no real waiting; fixtures keep delays at 0 so `ctx.sleep` has
+        // nothing left to spin on
+        fn wait_ms(&self, _: u64) {
+            std::thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn test_step_records() {
+        let acts = serde_json::from_str::<ActionList>(
+            r#"[{"act":"launch","target":"com.apple.Notes"},
+                {"act":"if","cond":{"var":"x"},
+                 "then":[{"act":"type","text":"yes"}],
+                 "else":[{"act":"keypress","key":"enter"},{"act":"wait","ms":1}]},
+                {"act":"repeat","times":2,"do":[{"act":"paste"}]},
+                {"act":"type","text":"{var.missing}"}]"#,
+        )
+        .unwrap()
+        .actions;
+        let ui = FakeUi::default();
+        let mut ctx = JobContext::default();
+        let failure = run_list(&ui, &acts, &mut ctx, "").unwrap_err();
+        assert_eq!(failure.step, "3");
+        let steps: Vec<_> = ctx.steps.iter().map(|r| r.step.as_str()).collect();
+        assert_eq!(steps, ["0", "1.0", "1.1", "2.0", "2.0", "3"]);
+        assert!(ctx.steps[5].error.as_deref().unwrap().contains("missing"));
+        assert_eq!(ui.0.lock().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_retries_and_defaults() {
+        let run = |json: &str| {
+            let list = serde_json::from_str::<ActionList>(json).unwrap();
+            let ui = FakeUi::default();
+            let mut ctx = JobContext {
+                defaults: list.defaults,
+                ..JobContext::default()
+            };
+            let res = run_list(&ui, &list.actions, &mut ctx, "");
+            (res, ctx.steps[0].attempts)
+        };
+        let (res, attempts) =
+            run(r#"[{"act":"keypress","key":"F13","retries":3,"retry_delay_ms":0}]"#);
+        assert!(res.is_ok());
+        assert_eq!(attempts, 3);
+
+        let (res, attempts) =
+            run(r#"[{"act":"keypress","key":"F13","retries":1,"retry_delay_ms":0}]"#);
+        assert!(res.is_err());
+        assert_eq!(attempts, 2);
+
+        let (res, _) = run(r#"{"defaults":{"retries":5,"retry_delay_ms":0},
+                "actions":[{"act":"keypress","key":"F13"}]}"#);
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn test_screenshot_artifact_records_step() {
+        let acts = serde_json::from_str::<ActionList>(
+            r#"[{"act":"paste"},
+                {"act":"repeat","times":1,"do":[{"act":"screenshot","name":"after_paste"}]}]"#,
+        )
+        .unwrap()
+        .actions;
+        let mut ctx = JobContext::default();
+        run_list(&FakeUi::default(), &acts, &mut ctx, "").unwrap();
+        assert_eq!(ctx.artifacts.len(), 1);
+        let art = &ctx.artifacts[0];
+        assert_eq!(art.step, "1.0");
+        assert_eq!(art.name.as_deref(), Some("after_paste"));
+        assert_eq!(art.content_type, "image/png");
+        assert!(art.data.starts_with(b"\x89PNG"));
+    }
+
+    #[test]
+    fn test_type_focuses_window_first() {
+        let acts = serde_json::from_str::<ActionList>(
+            r#"[{"act":"type","text":"hi","window":{"title":"Mail*"}},
+                {"act":"move_window","window":"front","x":0,"y":25},
+                {"act":"quit_app","target":"com.apple.Notes"}]"#,
+        )
+        .unwrap()
+        .actions;
+        let ui = FakeUi::default();
+        run_list(&ui, &acts, &mut JobContext::default(), "").unwrap();
+        assert_eq!(
+            *ui.0.lock().unwrap(),
+            [
+                "focus Title(\"Mail*\")",
+                "type hi",
+                "move_window 0,25",
+                "quit_app com.apple.Notes"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cancelled_job_stops_before_next_step() {
+        let acts = serde_json::from_str::<ActionList>(
+            r#"[{"act":"wait","ms":60000},{"act":"keypress","key":"a"}]"#,
+        )
+        .unwrap()
+        .actions;
+        let ui = FakeUi::default();
+        let mut ctx = JobContext::default();
+        ctx.cancel.store(true, Ordering::SeqCst);
+        // a cancelled wait returns at once instead of sleeping out its 60s
+        let started = Instant::now();
+        assert!(ctx.sleep(&ui, 60_000).is_err());
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        let err = run_list(&ui, &acts, &mut ctx, "").unwrap_err();
+        assert_eq!(err.step, "0");
+        assert_eq!(err.error, "job cancelled");
+        assert!(ctx.steps.is_empty());
+        assert!(ui.0.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_step_events_track_progress() {
+        let acts = serde_json::from_str::<ActionList>(
+            r#"[{"act":"keypress","key":"a"},{"act":"type","text":"{var.nope}"}]"#,
+        )
+        .unwrap()
+        .actions;
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let mut ctx = JobContext {
+            on_event: Some(Box::new(move |e: &JobEvent| {
+                sink.lock().unwrap().push(e.clone())
+            })),
+            ..JobContext::default()
+        };
+        run_list(&FakeUi::default(), &acts, &mut ctx, "").unwrap_err();
+
+        let events = events.lock().unwrap();
+        let mut job = JobResult::pending();
+        job.apply(&events[0]);
+        assert_eq!(job.current_step.as_deref(), Some("0"));
+        assert_eq!(job.steps[0].status, JobStatus::Running);
+        for e in &events[1..] {
+            job.apply(e);
+        }
+        assert_eq!(events.len(), 4);
+        assert_eq!(job.current_step, None);
+        let statuses: Vec<_> = job.steps.iter().map(|s| s.status).collect();
+        assert_eq!(statuses, [JobStatus::Success, JobStatus::Failed]);
+        assert_eq!(
+            job.steps[1].error.as_deref(),
+            Some("variable `nope` is not set")
+        );
+        let json = serde_json::to_value(&events[3]).unwrap();
+        assert_eq!(json["event"], "step_finished");
+        assert_eq!(json["status"], "Failed");
+    }
+
+    #[test]
+    fn test_job_retention_and_filters() {
+        let now = Utc::now();
+        let job = |hours_ago: i64, status: JobStatus| JobResult {
+            status,
+            created_at: now - chrono::Duration::hours(hours_ago),
+            finished_at: Some(now - chrono::Duration::hours(hours_ago)),
+            tags: vec!["nightly".into()],
+            ..JobResult::pending()
+        };
+        let jobs: HashMap<String, JobResult> = [
+            ("new".to_string(), job(1, JobStatus::Success)),
+            ("mid".to_string(), job(5, JobStatus::Failed)),
+            ("old".to_string(), job(50, JobStatus::Success)),
+            ("stuck".to_string(), job(60, JobStatus::Running)),
+        ]
+        .into();
+
+        let mut ids = expired(&jobs, Some(chrono::Duration::hours(24)), None, now);
+        ids.sort();
+        assert_eq!(ids, ["old"]);
+        // running jobs count towards the limit but are never evicted
+        let mut ids = expired(&jobs, None, Some(1), now);
+        ids.sort();
+        assert_eq!(ids, ["mid", "old"]);
+
+        let filter = JobFilter {
+            status: Some(JobStatus::Success),
+            since: Some(now - chrono::Duration::hours(24)),
+            tag: Some("nightly".into()),
+            ..JobFilter::default()
+        };
+        let hits: Vec<_> = jobs
+            .iter()
+            .filter(|(_, j)| filter.matches(j))
+            .map(|(id, _)| id.as_str())
+            .collect();
+        assert_eq!(hits, ["new"]);
+        let by_agent = JobFilter {
+            agent: Some("abc".into()),
+            ..JobFilter::default()
+        };
+        assert!(!by_agent.matches(&jobs["new"]));
+    }
+
+    #[test]
+    fn test_read_masks_labels() {
+        register_secret("s3cret-title");
+        let node = crate::tree::node("AXStaticText", "s3cret-title", Some("plain"), vec![]);
+        assert_eq!(
+            read_field(&node, ReadField::Label, "AXStaticText").unwrap(),
+            "***MASK***"
+        );
+        assert_eq!(
+            read_field(&node, ReadField::Value, "AXStaticText").unwrap(),
+            "plain"
+        );
+    }
+
+    #[test]
+    fn test_snapshot_artifacts_mask_labels() {
+        register_secret("s3cret-window");
+        let tree = crate::tree::node(
+            "AXWindow",
+            "s3cret-window",
+            None,
+            vec![crate::tree::node(
+                "AXButton",
+                "s3cret-window",
+                Some("ok"),
+                vec![],
+            )],
+        );
+        let masked = tree.masked();
+        assert_eq!(masked.label, "***MASK***");
+        assert_eq!(masked.children[0].label, "***MASK***");
+        assert_eq!(masked.children[0].value.as_deref(), Some("ok"));
+        let json = String::from_utf8(serde_json::to_vec(&masked).unwrap()).unwrap();
+        assert!(!json.contains("s3cret-window"));
+    }
+
+    #[test]
+    fn test_repeat_steps_survive_a_reload() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let store = Arc::new(JsonlStore::open(tmp.path()).unwrap());
+        let running = JobResult {
+            status: JobStatus::Running,
+            ..JobResult::pending()
+        };
+        store.save("a", &running).unwrap();
+        let acts = serde_json::from_str::<ActionList>(
+            r#"[{"act":"repeat","times":3,"do":[{"act":"wait","ms":0}]}]"#,
+        )
+        .unwrap()
+        .actions;
+        let sink = store.clone();
+        let mut ctx = JobContext {
+            on_event: Some(Box::new(move |event| {
+                if let JobEvent::StepFinished(record) = event {
+                    persist_step(&*sink, "a", record);
+                }
+            })),
+            ..JobContext::default()
+        };
+        run_list(&FakeUi::default(), &acts, &mut ctx, "").unwrap();
+        assert_eq!(ctx.steps.len(), 3);
+
+        let jobs = JsonlStore::open(tmp.path()).unwrap().load().unwrap();
+        let steps: Vec<_> = jobs[0].1.steps.iter().map(|s| s.step.as_str()).collect();
+        assert_eq!(steps, ["0.0", "0.0", "0.0"]);
+    }
+
+    #[test]
+    fn test_timeout_bounds_a_single_attempt() {
+        let acts = serde_json::from_str::<ActionList>(
+            r#"[{"act":"wait","ms":60000,"timeout_ms":50,"retries":3}]"#,
+        )
+        .unwrap()
+        .actions;
+        let mut ctx = JobContext::default();
+        let started = Instant::now();
+        let err = run_list(&FakeUi::default(), &acts, &mut ctx, "").unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(err.error, "step timed out (timeout_ms)");
+        assert_eq!(ctx.steps[0].attempts, 1);
+        assert_eq!(ctx.steps[0].status, JobStatus::Failed);
+        assert_eq!(ctx.deadline, None);
+    }
+
+    #[test]
+    fn test_element_acts() {
+        let acts = serde_json::from_str::<ActionList>(
+            r#"[{"act":"set_value","selector":"AXTextField","value":"Order {var.order}"},
+                {"act":"press","selector":"AXButton[label=\"OK\"]"},
+                {"act":"menu","path":"File > Export >PDF"}]"#,
+        )
+        .unwrap()
+        .actions;
+        let ui = FakeUi::default();
+        let mut ctx = JobContext::default();
+        ctx.vars.insert("order".into(), "A-1".into());
+        run_list(&ui, &acts, &mut ctx, "").unwrap();
+        assert_eq!(
+            *ui.0.lock().unwrap(),
+            [
+                "set_value Order A-1",
+                "press AXButton[label=\"OK\"]",
+                "menu File|Export|PDF"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_step_meta_in_records_and_failure() {
+        let acts = serde_json::from_str::<ActionList>(
+            r#"[{"act":"paste","id":"paste-body"},
+                {"act":"type","text":"{var.email}","id":"fill-email",
+                 "description":"type into login form","rationale":"the form needs a login"}]"#,
+        )
+        .unwrap()
+        .actions;
+        let mut ctx = JobContext::default();
+        let failure = run_list(&FakeUi::default(), &acts, &mut ctx, "").unwrap_err();
+        assert_eq!(
+            failure.to_string(),
+            "step `fill-email` (type into login form): variable `email` is not set"
+        );
+        assert_eq!(
+            ctx.steps[0].to_string().split(" ok").next(),
+            Some("step `paste-body` (paste):")
+        );
+        let json = serde_json::to_value(&failure).unwrap();
+        assert_eq!(json["id"], "fill-email");
+        assert_eq!(json["rationale"], "the form needs a login");
+        assert!(serde_json::to_value(&ctx.steps[0])
+            .unwrap()
+            .get("description")
+            .is_none());
+    }
+}
@@ -0,0 +1,378 @@
+// src/keymap.rs
+
+use crate::action::Modifier;
+use anyhow::{anyhow, bail, Result};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+/// Physical keyboard layout used to turn characters into key presses.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    #[default]
+    Us,
+    Jis,
+}
+
+/// How `type` enters its text.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TypeMode {
+    /// key presses from the layout table; other characters as Unicode events
+    #[default]
+    Keys,
+    /// every character as a Unicode keyboard event
+    Unicode,
+    /// through the clipboard and Cmd+V (the previous clipboard is restored)
+    Paste,
+}
+
+/// One key press: virtual keycode plus the modifiers needed for the character.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyStroke {
+    pub code: u16,
+    pub shift: bool,
+    pub option: bool,
+}
+
+impl KeyStroke {
+    const fn plain(code: u16) -> Self {
+        Self {
+            code,
+            shift: false,
+            option: false,
+        }
+    }
+
+    const fn shifted(code: u16) -> Self {
+        Self {
+            code,
+            shift: true,
+            option: false,
+        }
+    }
+}
+
+// ANSI positions shared by both layouts
+const LETTERS: [(char, u16); 26] = [
+    ('a', 0x00),
+    ('b', 0x0B),
+    ('c', 0x08),
+    ('d', 0x02),
+    ('e', 0x0E),
+    ('f', 0x03),
+    ('g', 0x05),
+    ('h', 0x04),
+    ('i', 0x22),
+    ('j', 0x26),
+    ('k', 0x28),
+    ('l', 0x25),
+    ('m', 0x2E),
+    ('n', 0x2D),
+    ('o', 0x1F),
+    ('p', 0x23),
+    ('q', 0x0C),
+    ('r', 0x0F),
+    ('s', 0x01),
+    ('t', 0x11),
+    ('u', 0x20),
+    ('v', 0x09),
+    ('w', 0x0D),
+    ('x', 0x07),
+    ('y', 0x10),
+    ('z', 0x06),
+];
+
+const DIGITS: [(char, u16); 10] = [
+    ('1', 0x12),
+    ('2', 0x13),
+    ('3', 0x14),
+    ('4', 0x15),
+    ('5', 0x17),
+    ('6', 0x16),
+    ('7', 0x1A),
+    ('8', 0x1C),
+    ('9', 0x19),
+    ('0', 0x1D),
+];
+
+/// (unshifted, shifted, keycode)
+const US_SYMBOLS: [(char, char, u16); 11] = [
+    ('-', '_', 0x1B),
+    ('=', '+', 0x18),
+    ('[', '{', 0x21),
+    (']', '}', 0x1E),
+    ('\\', '|', 0x2A),
+    (';', ':', 0x29),
+    ('\'', '"', 0x27),
+    (',', '<', 0x2B),
+    ('.', '>', 0x2F),
+    ('/', '?', 0x2C),
+    ('`', '~', 0x32),
+];
+const US_SHIFTED_DIGITS: [char; 10] = ['!', '@', '#', '$', '%', '^', '&', '*', '(', ')'];
+
+const JIS_SYMBOLS: [(char, char, u16); 10] = [
+    ('-', '=', 0x1B),
+    ('^', '~', 0x18),
+    ('@', '`', 0x21),
+    ('[', '{', 0x1E),
+    (']', '}', 0x2A),
+    (';', '+', 0x29),
+    (':', '*', 0x27),
+    (',', '<', 0x2B),
+    ('.', '>', 0x2F),
+    ('/', '?', 0x2C),
+];
+/// `0` has no shifted character on JIS
+const JIS_SHIFTED_DIGITS: [char; 9] = ['!', '"', '#', '$', '%', '&', '\'', '(', ')'];
+const JIS_YEN: u16 = 0x5D;
+const JIS_UNDERSCORE: u16 = 0x5E;
+
+impl Layout {
+    /// Key press that types `c`, or `None` if the layout has no key for it.
+    pub fn stroke(self, c: char) -> Option<KeyStroke> {
+        match c {
+            ' ' => return Some(KeyStroke::plain(0x31)),
+            '\n' => return Some(KeyStroke::plain(0x24)),
+            '\t' => return Some(KeyStroke::plain(0x30)),
+            _ => {}
+        }
+        if let Some(&(_, code)) = LETTERS.iter().find(|(l, _)| *l == c.to_ascii_lowercase()) {
+            return Some(if c.is_ascii_uppercase() {
+                KeyStroke::shifted(code)
+            } else {
+                KeyStroke::plain(code)
+            });
+        }
+        if let Some(&(_, code)) = DIGITS.iter().find(|(d, _)| *d == c) {
+            return Some(KeyStroke::plain(code));
+        }
+        let (symbols, shifted_digits): (&[(char, char, u16)], &[char]) = match self {
+            Layout::Us => (&US_SYMBOLS, &US_SHIFTED_DIGITS),
+            Layout::Jis => (&JIS_SYMBOLS, &JIS_SHIFTED_DIGITS),
+        };
+        if let Some(i) = shifted_digits.iter().position(|s| *s == c) {
+            return Some(KeyStroke::shifted(DIGITS[i].1));
+        }
+        for &(plain, shifted, code) in symbols {
+            if c == plain {
+                return Some(KeyStroke::plain(code));
+            }
+            if c == shifted {
+                return Some(KeyStroke::shifted(code));
+            }
+        }
+        match (self, c) {
+            (Layout::Jis, '¥') => Some(KeyStroke::plain(JIS_YEN)),
+            (Layout::Jis, '|') => Some(KeyStroke::shifted(JIS_YEN)),
+            (Layout::Jis, '\\') => Some(KeyStroke {
+                option: true,
+                ..KeyStroke::plain(JIS_YEN)
+            }),
+            (Layout::Jis, '_') => Some(KeyStroke::plain(JIS_UNDERSCORE)),
+            _ => None,
+        }
+    }
+}
+
+/// One key press with held modifiers, e.g. `CMD+SHIFT+S`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chord {
+    pub modifiers: Vec<Modifier>,
+    pub code: u16,
+}
+
+/// Named keys (lower case) and their virtual key codes.
+const NAMED_KEYS: [(&str, u16); 24] = [
+    ("enter", 0x24),
+    ("return", 0x24),
+    ("tab", 0x30),
+    ("space", 0x31),
+    ("backspace", 0x33),
+    ("delete", 0x33),
+    ("forwarddelete", 0x75),
+    ("del", 0x75),
+    ("esc", 0x35),
+    ("escape", 0x35),
+    ("left", 0x7B),
+    ("right", 0x7C),
+    ("down", 0x7D),
+    ("up", 0x7E),
+    ("arrowleft", 0x7B),
+    ("arrowright", 0x7C),
+    ("arrowdown", 0x7D),
+    ("arrowup", 0x7E),
+    ("home", 0x73),
+    ("end", 0x77),
+    ("pageup", 0x74),
+    ("pagedown", 0x79),
+    ("help", 0x72),
+    ("capslock", 0x39),
+];
+
+/// F1..F20; macOS defines no virtual key codes above F20.
+const FUNCTION_KEYS: [u16; 20] = [
+    0x7A, 0x78, 0x63, 0x76, 0x60, 0x61, 0x62, 0x64, 0x65, 0x6D, 0x67, 0x6F, 0x69, 0x6B, 0x71, 0x6A,
+    0x40, 0x4F, 0x50, 0x5A,
+];
+
+/// Parse a key spec: chords separated by `,`, each a `+`-joined list of
+/// modifiers (`cmd`, `ctrl`, `alt`/`opt`, `shift`, any case) ending in a
+/// named key, `F1`..`F20` or a single character, e.g. `"CMD+K, CMD+S"`.
+/// Characters that need Shift on a US keyboard (`CMD+?`) add it.
+pub fn parse_keys(spec: &str) -> Result<Vec<Chord>> {
+    let mut chords = Vec::new();
+    let mut start = 0;
+    for (i, c) in spec.char_indices() {
+        let before = spec[start..i].trim_end();
+        // a `,` right after `+` (or alone) is the comma key itself
+        if c == ',' && !before.is_empty() && !before.ends_with('+') {
+            chords.push(parse_chord(&spec[start..i])?);
+            start = i + 1;
+        }
+    }
+    chords.push(parse_chord(&spec[start..])?);
+    Ok(chords)
+}
+
+fn parse_chord(chord: &str) -> Result<Chord> {
+    let chord = chord.trim();
+    let (mods, key) = if chord == "+" {
+        ("", "+")
+    } else if let Some(mods) = chord.strip_suffix("++") {
+        (mods, "+")
+    } else {
+        chord.rsplit_once('+').unwrap_or(("", chord))
+    };
+    let mut modifiers = Vec::new();
+    for m in mods.split('+').map(str::trim).filter(|m| !m.is_empty()) {
+        modifiers.push(
+            parse_modifier(m).ok_or_else(|| anyhow!("unknown modifier `{}` in `{}`", m, chord))?,
+        );
+    }
+    let key = key.trim();
+    let code = match key_code(key, &mut modifiers) {
+        Some(code) => code,
+        None if key.is_empty() => bail!("missing key in `{}`", chord),
+        None if matches!(function_key(key), Some(21..=24)) => {
+            bail!("`{}` has no key code on macOS (F1..F20 only)", key)
+        }
+        None => bail!("unknown key `{}`", key),
+    };
+    Ok(Chord { modifiers, code })
+}
+
+fn parse_modifier(name: &str) -> Option<Modifier> {
+    match name.to_ascii_lowercase().as_str() {
+        "cmd" | "command" => Some(Modifier::Cmd),
+        "shift" => Some(Modifier::Shift),
+        "ctrl" | "control" => Some(Modifier::Ctrl),
+        "alt" | "opt" | "option" => Some(Modifier::Alt),
+        _ => None,
+    }
+}
+
+/// `12` for `F12` / `f12`.
+fn function_key(key: &str) -> Option<usize> {
+    key.strip_prefix(['f', 'F'])?.parse().ok()
+}
+
+pub fn modifier_keycode(m: Modifier) -> u16 {
+    match m {
+        Modifier::Cmd => 0x37,
+        Modifier::Shift => 0x38,
+        Modifier::Alt => 0x3A,
+        Modifier::Ctrl => 0x3B,
+    }
+}
+
+fn key_code(key: &str, modifiers: &mut Vec<Modifier>) -> Option<u16> {
+    let lower = key.to_ascii_lowercase();
+    if let Some(m) = parse_modifier(&lower) {
+        // a lone modifier, e.g. `shift`
+        return Some(modifier_keycode(m));
+    }
+    if let Some(&(_, code)) = NAMED_KEYS.iter().find(|(name, _)| *name == lower) {
+        return Some(code);
+    }
+    if let Some(n) = function_key(&lower) {
+        return n.checked_sub(1).and_then(|i| FUNCTION_KEYS.get(i)).copied();
+    }
+    let mut chars = lower.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => {
+            let stroke = Layout::Us.stroke(c).filter(|s| !s.option)?;
+            if stroke.shift && !modifiers.contains(&Modifier::Shift) {
+                modifiers.push(Modifier::Shift);
+            }
+            Some(stroke.code)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_us_shift_symbols() {
+        let us = Layout::Us;
+        assert_eq!(us.stroke('a'), Some(KeyStroke::plain(0x00)));
+        assert_eq!(us.stroke('A'), Some(KeyStroke::shifted(0x00)));
+        assert_eq!(us.stroke('@'), Some(KeyStroke::shifted(0x13)));
+        assert_eq!(us.stroke('!'), Some(KeyStroke::shifted(0x12)));
+        assert_eq!(us.stroke(':'), Some(KeyStroke::shifted(0x29)));
+        assert_eq!(us.stroke('"'), Some(KeyStroke::shifted(0x27)));
+        assert_eq!(us.stroke('あ'), None);
+    }
+
+    #[test]
+    fn test_jis_differs_from_us() {
+        let jis = Layout::Jis;
+        assert_eq!(jis.stroke('@'), Some(KeyStroke::plain(0x21)));
+        assert_eq!(jis.stroke(':'), Some(KeyStroke::plain(0x27)));
+        assert_eq!(jis.stroke('"'), Some(KeyStroke::shifted(0x13)));
+        assert_eq!(jis.stroke('('), Some(KeyStroke::shifted(0x1C)));
+        assert_eq!(jis.stroke('_'), Some(KeyStroke::plain(0x5E)));
+        assert!(jis.stroke('\\').unwrap().option);
+
+        // every printable ASCII character is typeable on both layouts
+        for c in ' '..='~' {
+            assert!(Layout::Us.stroke(c).is_some(), "us {:?}", c);
+            assert!(jis.stroke(c).is_some(), "jis {:?}", c);
+        }
+    }
+
+    #[test]
+    fn test_parse_key_specs() {
+        let chords = parse_keys("CMD+K, cmd+shift+S").unwrap();
+        assert_eq!(
+            chords,
+            [
+                Chord {
+                    modifiers: vec![Modifier::Cmd],
+                    code: 0x28
+                },
+                Chord {
+                    modifiers: vec![Modifier::Cmd, Modifier::Shift],
+                    code: 0x01
+                },
+            ]
+        );
+        assert_eq!(parse_keys("opt+F12").unwrap()[0].code, 0x6F);
+        assert_eq!(parse_keys("ctrl+pagedown").unwrap()[0].code, 0x79);
+        assert_eq!(parse_keys("Escape").unwrap()[0].modifiers, []);
+
+        // comma and plus as keys, shifted characters
+        assert_eq!(parse_keys("cmd+,").unwrap()[0].code, 0x2B);
+        let plus = &parse_keys("cmd++").unwrap()[0];
+        assert_eq!(plus.code, 0x18);
+        assert_eq!(plus.modifiers, [Modifier::Cmd, Modifier::Shift]);
+
+        for bad in ["cmd+", "hyper+a", "F24", "f0", "cmd+enterr", "あ"] {
+            assert!(parse_keys(bad).is_err(), "{}", bad);
+        }
+    }
+}
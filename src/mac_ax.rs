@@ -1,10 +1,28 @@
+use crate::action::{Modifier, Point, ScrollUnit};
 use crate::adapter::UiAdapter;
-use anyhow::{anyhow, Result};
+use crate::keymap::{modifier_keycode, Chord, KeyStroke, Layout, TypeMode};
+use crate::selector::resolve_target;
+use crate::tree::{Rect, WindowSelector};
+use anyhow::{anyhow, bail, Result};
 use core_graphics::{
-    event::{CGEvent, CGEventFlags, CGEventTapLocation, CGEventType, CGMouseButton},
+    display::CGDisplay,
+    event::{
+        CGEvent, CGEventFlags, CGEventTapLocation, CGEventType, CGMouseButton, EventField,
+        ScrollEventUnit,
+    },
     event_source::{CGEventSource, CGEventSourceStateID},
     geometry::CGPoint,
 };
+use std::io::Write;
+
+const DRAG_STEP_MS: u64 = 16;
+const KEY_DELAY_MS: u64 = 10;
+const SCROLL_STEP_MS: u64 = 16;
+const SCROLL_STEP_PX: i32 = 100;
+const SCROLL_STEP_LINES: i32 = 5;
+/// a page scroll keeps a little of the previous page in view
+const PAGE_OVERLAP: f64 = 0.9;
+const PASTE_SETTLE_MS: u64 = 150;
 
 pub struct MacAdapter;
 
@@ -12,6 +30,19 @@ impl MacAdapter {
     pub fn new() -> Self {
         Self
     }
+
+    /// Cmd+V `text`, then put back whatever was on the clipboard.
+    fn paste_text(&self, text: &str) -> Result<()> {
+        let previous = self.clipboard_get().ok();
+        self.clipboard_set(text)?;
+        self.paste()?;
+        // give the target app time to read the pasteboard
+        self.wait_ms(PASTE_SETTLE_MS);
+        if let Some(previous) = previous {
+            self.clipboard_set(&previous)?;
+        }
+        Ok(())
+    }
 }
 
 impl UiAdapter for MacAdapter {
@@ -24,99 +55,234 @@ impl UiAdapter for MacAdapter {
         Ok(())
     }
 
-    fn click(&self, _sel: Option<&str>, x: Option<i32>, y: Option<i32>) -> Result<()> {
-        let (x, y) = (x.unwrap_or(100), y.unwrap_or(100));
-        let src = CGEventSource::new(CGEventSourceStateID::HIDSystemState)
-            .map_err(|_| anyhow!("CGEventSource::new failed"))?;
-        let mut modifiers = CGEventFlags::empty();
-        let code = str_to_keycode("key_string", &mut modifiers)?;
-        let up_event = CGEvent::new_keyboard_event(src.clone(), code, false)
-            .map_err(|_| anyhow::anyhow!("Failed to create keyboard event"))?;
-        let pos = CGPoint::new(x as f64, y as f64);
-        let down_event = CGEvent::new_mouse_event(
-            src.clone(),
-            CGEventType::LeftMouseDown,
-            pos,
+    fn open_url(&self, url: &str, browser: Option<&str>) -> Result<()> {
+        let mut cmd = std::process::Command::new("open");
+        if let Some(bundle) = browser {
+            cmd.arg("-b").arg(bundle);
+        }
+        let status = cmd.arg(url).spawn()?.wait()?;
+        if !status.success() {
+            return Err(anyhow!("open {} failed ({:?})", url, status.code()));
+        }
+        Ok(())
+    }
+
+    fn click(&self, at: &Point, modifiers: &[Modifier]) -> Result<()> {
+        mouse_click(resolve(at)?, false, 1, modifiers)
+    }
+
+    fn double_click(&self, at: &Point, modifiers: &[Modifier]) -> Result<()> {
+        let pos = resolve(at)?;
+        mouse_click(pos, false, 1, modifiers)?;
+        mouse_click(pos, false, 2, modifiers)
+    }
+
+    fn right_click(&self, at: &Point, modifiers: &[Modifier]) -> Result<()> {
+        mouse_click(resolve(at)?, true, 1, modifiers)
+    }
+
+    fn move_to(&self, at: &Point) -> Result<()> {
+        post_mouse(
+            CGEventType::MouseMoved,
+            resolve(at)?,
             CGMouseButton::Left,
+            CGEventFlags::empty(),
+            0,
         )
-        .map_err(|_| anyhow!("CGEvent create error"))?;
-        let up_event =
-            CGEvent::new_mouse_event(src, CGEventType::LeftMouseUp, pos, CGMouseButton::Left)
-                .map_err(|_| anyhow!("CGEvent create error"))?;
+    }
 
-        down_event.post(CGEventTapLocation::HID);
-        up_event.post(CGEventTapLocation::HID);
+    fn drag(
+        &self,
+        from: &Point,
+        to: &Point,
+        duration_ms: u64,
+        modifiers: &[Modifier],
+        stop: &dyn Fn() -> bool,
+    ) -> Result<()> {
+        let (a, b) = (resolve(from)?, resolve(to)?);
+        let flags = modifier_flags(modifiers);
+        post_mouse(CGEventType::LeftMouseDown, a, CGMouseButton::Left, flags, 1)?;
+        // intermediate drag events so that apps see a continuous movement
+        let steps = (duration_ms / DRAG_STEP_MS).max(1);
+        for i in 1..=steps {
+            let t = i as f64 / steps as f64;
+            let p = CGPoint::new(a.x + (b.x - a.x) * t, a.y + (b.y - a.y) * t);
+            if stop() {
+                // never leave the button held down
+                post_mouse(CGEventType::LeftMouseUp, p, CGMouseButton::Left, flags, 1)?;
+                bail!("drag interrupted");
+            }
+            post_mouse(
+                CGEventType::LeftMouseDragged,
+                p,
+                CGMouseButton::Left,
+                flags,
+                1,
+            )?;
+            std::thread::sleep(std::time::Duration::from_millis(duration_ms / steps));
+        }
+        post_mouse(CGEventType::LeftMouseUp, b, CGMouseButton::Left, flags, 1)
+    }
 
+    fn scroll(&self, at: &Point, dx: i32, dy: i32, unit: ScrollUnit) -> Result<()> {
+        // move over the target first, if any; an element's frame is the page size
+        let frame = if at.selector.is_none() && at.x.is_none() && at.y.is_none() {
+            None
+        } else {
+            let (pos, frame) = resolve_framed(at)?;
+            post_mouse(
+                CGEventType::MouseMoved,
+                pos,
+                CGMouseButton::Left,
+                CGEventFlags::empty(),
+                0,
+            )?;
+            frame.map(|rect| (rect.width, rect.height))
+        };
+        let (dx, dy, cg_unit, step) = match unit {
+            ScrollUnit::Pixel => (dx, dy, ScrollEventUnit::PIXEL, SCROLL_STEP_PX),
+            ScrollUnit::Line => (dx, dy, ScrollEventUnit::LINE, SCROLL_STEP_LINES),
+            ScrollUnit::Page => {
+                let (w, h) = frame.unwrap_or_else(|| {
+                    let size = CGDisplay::main().bounds().size;
+                    (size.width, size.height)
+                });
+                let px = |n: i32, len: f64| (n as f64 * len * PAGE_OVERLAP).round() as i32;
+                (px(dx, w), px(dy, h), ScrollEventUnit::PIXEL, SCROLL_STEP_PX)
+            }
+        };
+        let src = CGEventSource::new(CGEventSourceStateID::HIDSystemState)
+            .map_err(|_| anyhow!("CGEventSource::new failed"))?;
+        // several wheel ticks, like a real wheel, instead of one huge delta
+        let (mut rest_x, mut rest_y) = (dx, dy);
+        while rest_x != 0 || rest_y != 0 {
+            let sx = rest_x.clamp(-step, step);
+            let sy = rest_y.clamp(-step, step);
+            // wheel1 is vertical, wheel2 horizontal; positive scrolls up / left
+            let event = CGEvent::new_scroll_event(src.clone(), cg_unit, 2, -sy, -sx, 0)
+                .map_err(|_| anyhow!("Failed to create scroll event"))?;
+            event.post(CGEventTapLocation::HID);
+            rest_x -= sx;
+            rest_y -= sy;
+            std::thread::sleep(std::time::Duration::from_millis(SCROLL_STEP_MS));
+        }
         Ok(())
     }
 
-    fn scroll(&self, dy: i32) -> Result<()> {
-        let key = if dy < 0 { 0x74 } else { 0x79 };
+    fn keypress(&self, chord: &Chord) -> Result<()> {
         let src = CGEventSource::new(CGEventSourceStateID::HIDSystemState)
-            .map_err(|_| anyhow!("CGEventSource error"))?;
-        let down = CGEvent::new_keyboard_event(src.clone(), key, true)
-            .map_err(|_| anyhow!("CGEvent create error"))?;
-        let up = CGEvent::new_keyboard_event(src, key, false)
-            .map_err(|_| anyhow!("CGEvent create error"))?;
-        down.post(CGEventTapLocation::HID);
-        up.post(CGEventTapLocation::HID);
+            .map_err(|_| anyhow!("CGEventSource::new failed"))?;
+        let flags = modifier_flags(&chord.modifiers);
+        let mod_codes: Vec<u16> = chord
+            .modifiers
+            .iter()
+            .map(|m| modifier_keycode(*m))
+            .collect();
+        // modifiers down, key down/up with their flags, modifiers up in reverse
+        let mut events: Vec<(u16, bool)> = mod_codes.iter().map(|c| (*c, true)).collect();
+        events.push((chord.code, true));
+        events.push((chord.code, false));
+        events.extend(mod_codes.iter().rev().map(|c| (*c, false)));
+        for (code, down) in events {
+            let event = CGEvent::new_keyboard_event(src.clone(), code, down)
+                .map_err(|_| anyhow!("Failed to create keyboard event"))?;
+            event.set_flags(flags);
+            event.post(CGEventTapLocation::HID);
+            std::thread::sleep(std::time::Duration::from_millis(KEY_DELAY_MS));
+        }
         Ok(())
     }
 
-    fn keypress(&self, key: &str) -> Result<()> {
+    fn type_text(&self, text: &str, mode: TypeMode, layout: Layout) -> Result<()> {
+        if mode == TypeMode::Paste {
+            return self.paste_text(text);
+        }
         let src = CGEventSource::new(CGEventSourceStateID::HIDSystemState)
             .map_err(|_| anyhow!("CGEventSource::new failed"))?;
-        let mut modifiers = CGEventFlags::empty();
+        for c in text.chars() {
+            match layout.stroke(c).filter(|_| mode == TypeMode::Keys) {
+                Some(stroke) => post_stroke(&src, stroke)?,
+                None => post_unicode(&src, c)?,
+            }
+        }
+        Ok(())
+    }
 
-        for kc in key.split('+') {
-            let code = str_to_keycode(kc, &mut modifiers)?;
-            let down_event = CGEvent::new_keyboard_event(src.clone(), code, true)
-                .map_err(|_| anyhow!("Failed to create keyboard event"))?;
-            down_event.post(CGEventTapLocation::HID);
+    fn clipboard_set(&self, text: &str) -> Result<()> {
+        let mut child = std::process::Command::new("pbcopy")
+            .stdin(std::process::Stdio::piped())
+            .spawn()?;
+        child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow!("pbcopy stdin unavailable"))?
+            .write_all(text.as_bytes())?;
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(anyhow!("pbcopy failed ({:?})", status.code()));
         }
+        Ok(())
+    }
 
-        for kc in key.split('+').rev() {
-            let code = str_to_keycode(kc, &mut modifiers)?;
-            let up_event = CGEvent::new_keyboard_event(src.clone(), code, false)
-                .map_err(|_| anyhow::anyhow!("Failed to create keyboard event"))?;
-            up_event.post(CGEventTapLocation::HID);
+    fn clipboard_get(&self) -> Result<String> {
+        let output = std::process::Command::new("pbpaste").output()?;
+        if !output.status.success() {
+            return Err(anyhow!("pbpaste failed ({:?})", output.status.code()));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    fn paste(&self) -> Result<()> {
+        // Cmd+V
+        let src = CGEventSource::new(CGEventSourceStateID::HIDSystemState)
+            .map_err(|_| anyhow!("CGEventSource::new failed"))?;
+        for down in [true, false] {
+            let event = CGEvent::new_keyboard_event(src.clone(), 0x09, down)
+                .map_err(|_| anyhow!("Failed to create keyboard event"))?;
+            event.set_flags(CGEventFlags::CGEventFlagCommand);
+            event.post(CGEventTapLocation::HID);
         }
         Ok(())
     }
 
-    fn type_text(&self, text: &str) -> Result<()> {
-        for c in text.chars() {
-            let is_upper = c.is_ascii_uppercase();
-            let base_c = c.to_ascii_lowercase();
-
-            let code = match character_to_keycode(base_c) {
-                Some(k) => k,
-                None => {
-                    return Err(anyhow!("unsupported char '{}'", c));
-                }
-            };
-
-            let mut flags = CGEventFlags::empty();
-            if is_upper {
-                flags |= CGEventFlags::CGEventFlagShift;
-            }
+    fn press(&self, selector: &str, window: &WindowSelector) -> Result<()> {
+        crate::element::press(selector, window)
+    }
 
-            let src = CGEventSource::new(CGEventSourceStateID::HIDSystemState)
-                .map_err(|e| anyhow!("CGEventSource::new failed"))?;
+    fn set_value(&self, selector: &str, window: &WindowSelector, value: &str) -> Result<()> {
+        crate::element::set_value(selector, window, value)
+    }
 
-            let down = CGEvent::new_keyboard_event(src.clone(), code, true)
-                .map_err(|e| anyhow!("key-down failed"))?;
-            down.set_flags(flags);
-            down.post(CGEventTapLocation::HID);
+    fn menu(&self, path: &[&str]) -> Result<()> {
+        crate::element::pick_menu(path)
+    }
 
-            std::thread::sleep(std::time::Duration::from_millis(10));
+    fn focus_window(&self, window: &WindowSelector) -> Result<()> {
+        crate::window::focus(window)
+    }
 
-            let up = CGEvent::new_keyboard_event(src, code, false)
-                .map_err(|e| anyhow!("key-up failed"))?;
-            up.set_flags(flags);
-            up.post(CGEventTapLocation::HID);
-        }
-        Ok(())
+    fn move_window(&self, window: &WindowSelector, x: i32, y: i32) -> Result<()> {
+        crate::window::move_to(window, x, y)
+    }
+
+    fn resize_window(&self, window: &WindowSelector, width: u32, height: u32) -> Result<()> {
+        crate::window::resize(window, width, height)
+    }
+
+    fn minimize(&self, window: &WindowSelector) -> Result<()> {
+        crate::window::minimize(window)
+    }
+
+    fn close_window(&self, window: &WindowSelector) -> Result<()> {
+        crate::window::close(window)
+    }
+
+    fn quit_app(&self, target: &str) -> Result<()> {
+        crate::window::quit_app(target)
+    }
+
+    fn screenshot(&self) -> Result<Vec<u8>> {
+        crate::screenshot::capture_png()
     }
 
     fn wait_ms(&self, ms: u64) {
@@ -124,79 +290,104 @@ impl UiAdapter for MacAdapter {
     }
 }
 
-fn character_to_keycode(c: char) -> Option<u16> {
-    match c {
-        'a' => Some(0x00),
-        'b' => Some(0x0B),
-        'c' => Some(0x08),
-        'd' => Some(0x02),
-        'e' => Some(0x0E),
-        'f' => Some(0x03),
-        'g' => Some(0x05),
-        'h' => Some(0x04),
-        'i' => Some(0x22),
-        'j' => Some(0x26),
-        'k' => Some(0x28),
-        'l' => Some(0x25),
-        'm' => Some(0x2E),
-        'n' => Some(0x2D),
-        'o' => Some(0x1F),
-        'p' => Some(0x23),
-        'q' => Some(0x0C),
-        'r' => Some(0x0F),
-        's' => Some(0x01),
-        't' => Some(0x11),
-        'u' => Some(0x20),
-        'v' => Some(0x09),
-        'w' => Some(0x0D),
-        'x' => Some(0x07),
-        'y' => Some(0x10),
-        'z' => Some(0x06),
-        ' ' => Some(0x31),
-        '1' => Some(0x12),
-        '2' => Some(0x13),
-        '3' => Some(0x14),
-        '4' => Some(0x15),
-        '5' => Some(0x17),
-        '6' => Some(0x16),
-        '7' => Some(0x1A),
-        '8' => Some(0x1C),
-        '9' => Some(0x19),
-        '0' => Some(0x1D),
-        '-' => Some(0x1B),
-        '=' => Some(0x18),
-        '[' => Some(0x21),
-        ']' => Some(0x1E),
-        '\\' => Some(0x2A),
-        ';' => Some(0x29),
-        '\'' => Some(0x27),
-        ',' => Some(0x2B),
-        '.' => Some(0x2F),
-        '/' => Some(0x2C),
-        '`' => Some(0x32),
-        _ => None,
-    }
+fn resolve(at: &Point) -> Result<CGPoint> {
+    Ok(resolve_framed(at)?.0)
 }
 
-fn str_to_keycode(s: &str, modifiers: &mut CGEventFlags) -> Result<u16, anyhow::Error> {
-    match s.to_lowercase().as_str() {
-        "shift" => {
-            *modifiers |= CGEventFlags::CGEventFlagShift;
-            Ok(0x38)
-        }
-        "control" => {
-            *modifiers |= CGEventFlags::CGEventFlagControl;
-            Ok(0x3B)
+/// Screen point for `at`, plus the frame of the element it names, if any.
+/// Selector targets are checked against `click_bounds` here.
+fn resolve_framed(at: &Point) -> Result<(CGPoint, Option<Rect>)> {
+    let ((x, y), frame) = match (&at.selector, at.x, at.y) {
+        (Some(sel), _, _) => {
+            let (pos, rect) = resolve_target(sel)?;
+            (pos, Some(rect))
         }
-        "option" => {
-            *modifiers |= CGEventFlags::CGEventFlagAlternate;
-            Ok(0x3A)
-        }
-        "command" => {
-            *modifiers |= CGEventFlags::CGEventFlagCommand;
-            Ok(0x37)
-        }
-        other => character_to_keycode(other.chars().next().unwrap())
-            .ok_or_else(|| anyhow!("Key '{}' not supported", other)),
+        (None, Some(x), Some(y)) => ((x, y), None),
+        _ => bail!("a point needs both `x` and `y`, or a `selector`"),
+    };
+    Ok((CGPoint::new(x as f64, y as f64), frame))
+}
+
+fn modifier_flags(modifiers: &[Modifier]) -> CGEventFlags {
+    modifiers
+        .iter()
+        .fold(CGEventFlags::empty(), |flags, m| match m {
+            Modifier::Cmd => flags | CGEventFlags::CGEventFlagCommand,
+            Modifier::Shift => flags | CGEventFlags::CGEventFlagShift,
+            Modifier::Ctrl => flags | CGEventFlags::CGEventFlagControl,
+            Modifier::Alt => flags | CGEventFlags::CGEventFlagAlternate,
+        })
+}
+
+fn post_mouse(
+    ty: CGEventType,
+    pos: CGPoint,
+    button: CGMouseButton,
+    flags: CGEventFlags,
+    click_state: i64,
+) -> Result<()> {
+    let src = CGEventSource::new(CGEventSourceStateID::HIDSystemState)
+        .map_err(|_| anyhow!("CGEventSource::new failed"))?;
+    let event = CGEvent::new_mouse_event(src, ty, pos, button)
+        .map_err(|_| anyhow!("CGEvent create error"))?;
+    event.set_flags(flags);
+    if click_state > 0 {
+        event.set_integer_value_field(EventField::MOUSE_EVENT_CLICK_STATE, click_state);
+    }
+    event.post(CGEventTapLocation::HID);
+    Ok(())
+}
+
+/// Press and release one mouse button; `click_state` 2 makes it the second
+/// half of a double-click.
+fn mouse_click(pos: CGPoint, right: bool, click_state: i64, modifiers: &[Modifier]) -> Result<()> {
+    let (down, up, button) = if right {
+        (
+            CGEventType::RightMouseDown,
+            CGEventType::RightMouseUp,
+            CGMouseButton::Right,
+        )
+    } else {
+        (
+            CGEventType::LeftMouseDown,
+            CGEventType::LeftMouseUp,
+            CGMouseButton::Left,
+        )
+    };
+    let flags = modifier_flags(modifiers);
+    post_mouse(down, pos, button, flags, click_state)?;
+    post_mouse(up, pos, button, flags, click_state)
+}
+
+/// Press and release one layout key with the modifiers it needs.
+fn post_stroke(src: &CGEventSource, stroke: KeyStroke) -> Result<()> {
+    let mut flags = CGEventFlags::empty();
+    if stroke.shift {
+        flags |= CGEventFlags::CGEventFlagShift;
+    }
+    if stroke.option {
+        flags |= CGEventFlags::CGEventFlagAlternate;
+    }
+    for down in [true, false] {
+        let event = CGEvent::new_keyboard_event(src.clone(), stroke.code, down)
+            .map_err(|_| anyhow!("Failed to create keyboard event"))?;
+        event.set_flags(flags);
+        event.post(CGEventTapLocation::HID);
+        std::thread::sleep(std::time::Duration::from_millis(KEY_DELAY_MS));
+    }
+    Ok(())
+}
+
+/// Type `c` as a Unicode keyboard event, independent of the active layout.
+fn post_unicode(src: &CGEventSource, c: char) -> Result<()> {
+    let mut buf = [0u16; 2];
+    let units = c.encode_utf16(&mut buf);
+    for down in [true, false] {
+        let event = CGEvent::new_keyboard_event(src.clone(), 0, down)
+            .map_err(|_| anyhow!("Failed to create keyboard event"))?;
+        event.set_string_from_utf16_unchecked(units);
+        event.post(CGEventTapLocation::HID);
+        std::thread::sleep(std::time::Duration::from_millis(KEY_DELAY_MS));
     }
+    Ok(())
 }
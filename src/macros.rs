@@ -0,0 +1,299 @@
+// src/macros.rs
+
+use crate::action::{Action, ActionList, Step, StepMeta};
+use anyhow::{anyhow, bail, Context, Result};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const MACRO_DIR: &str = ".thin-sag/macros";
+const MAX_CALL_DEPTH: usize = 8;
+
+static NAME_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[a-zA-Z0-9_-]+$").unwrap());
+static ARG_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{arg\.([a-zA-Z0-9_-]+)\}").unwrap());
+
+/// One macro in `~/.thin-sag/macros/<file>.yaml`, called as `<file>.<name>`:
+///
+/// ```yaml
+/// new_note:
+///   params:
+///     title: { type: string }
+///     count: { type: int, default: 1 }
+///     login: { type: secret-ref }
+///   actions:
+///     - { act: launch, target: com.apple.Notes }
+///     - { act: type, text: "{arg.title}" }
+/// ```
+///
+/// `{arg.x}` is replaced in every string of `actions`; a string that is just
+/// `{arg.x}` takes the argument's JSON type (so ints can fill `times`, `ms`...).
+/// `secret-ref` arguments are secret labels and become `{secret.<label>}`.
+#[derive(Debug, Deserialize)]
+pub struct MacroDef {
+    #[serde(default)]
+    pub params: BTreeMap<String, ParamSpec>,
+    pub actions: Vec<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ParamSpec {
+    #[serde(rename = "type")]
+    pub ty: ParamType,
+    pub default: Option<Value>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum ParamType {
+    String,
+    Int,
+    SecretRef,
+}
+
+/// Replace every `call` step (including inside blocks) with the macro body.
+/// Options, `description` and `rationale` set on the `call` apply to body
+/// steps that do not set their own; body step ids become `<call id>.<id>`.
+pub fn expand_macros(list: ActionList) -> Result<ActionList> {
+    let dir = dirs::home_dir()
+        .unwrap_or(PathBuf::from("/"))
+        .join(MACRO_DIR);
+    Ok(ActionList {
+        defaults: list.defaults,
+        actions: expand_with(&dir, list.actions, 0)?,
+    })
+}
+
+fn expand_with(dir: &Path, steps: Vec<Step>, depth: usize) -> Result<Vec<Step>> {
+    let mut out = Vec::with_capacity(steps.len());
+    for Step { meta, act, options } in steps {
+        let act = match act {
+            Action::Call { name, args } => {
+                if depth >= MAX_CALL_DEPTH {
+                    bail!(
+                        "macro `{}`: calls nested deeper than {}",
+                        name,
+                        MAX_CALL_DEPTH
+                    );
+                }
+                let def = load_macro(dir, &name)?;
+                let body = instantiate(&def, &args).with_context(|| format!("macro `{}`", name))?;
+                out.extend(
+                    expand_with(dir, body, depth + 1)?
+                        .into_iter()
+                        .map(|s| Step {
+                            meta: inherit_meta(&meta, s.meta),
+                            options: s.options.or(options),
+                            ..s
+                        }),
+                );
+                continue;
+            }
+            Action::If {
+                cond,
+                then,
+                otherwise,
+            } => Action::If {
+                cond,
+                then: expand_with(dir, then, depth)?,
+                otherwise: expand_with(dir, otherwise, depth)?,
+            },
+            Action::Repeat { times, cond, body } => Action::Repeat {
+                times,
+                cond,
+                body: expand_with(dir, body, depth)?,
+            },
+            other => other,
+        };
+        out.push(Step { meta, act, options });
+    }
+    Ok(out)
+}
+
+fn inherit_meta(call: &StepMeta, step: StepMeta) -> StepMeta {
+    StepMeta {
+        id: match (&call.id, step.id) {
+            (Some(call_id), Some(id)) => Some(format!("{}.{}", call_id, id)),
+            (_, id) => id,
+        },
+        description: step.description.or_else(|| call.description.clone()),
+        rationale: step.rationale.or_else(|| call.rationale.clone()),
+    }
+}
+
+fn load_macro(dir: &Path, name: &str) -> Result<MacroDef> {
+    let (file, item) = name
+        .split_once('.')
+        .ok_or_else(|| anyhow!("macro name `{}` must be `<file>.<macro>`", name))?;
+    if !NAME_REGEX.is_match(file) {
+        bail!("invalid macro file name `{}`", file);
+    }
+    let path = dir.join(format!("{}.yaml", file));
+    let txt = fs::read_to_string(&path)
+        .with_context(|| format!("macro file {} not found", path.display()))?;
+    let mut defs: BTreeMap<String, MacroDef> = serde_yaml::from_str(&txt)
+        .with_context(|| format!("invalid macro file {}", path.display()))?;
+    defs.remove(item)
+        .ok_or_else(|| anyhow!("macro `{}` not found in {}", name, path.display()))
+}
+
+/// Check `args` against the declared params and substitute them into the body.
+fn instantiate(def: &MacroDef, args: &Map<String, Value>) -> Result<Vec<Step>> {
+    if let Some(unknown) = args.keys().find(|k| !def.params.contains_key(*k)) {
+        bail!("unknown argument `{}`", unknown);
+    }
+    let mut values = BTreeMap::new();
+    for (name, spec) in &def.params {
+        let v = args
+            .get(name)
+            .or(spec.default.as_ref())
+            .ok_or_else(|| anyhow!("missing argument `{}`", name))?;
+        let v = match (spec.ty, v) {
+            (ParamType::String, Value::String(_)) => v.clone(),
+            (ParamType::Int, Value::Number(n)) if n.is_i64() || n.is_u64() => v.clone(),
+            (ParamType::SecretRef, Value::String(label)) if NAME_REGEX.is_match(label) => {
+                Value::String(format!("{{secret.{}}}", label))
+            }
+            (ty, v) => bail!("argument `{}`: expected {:?}, got {}", name, ty, v),
+        };
+        values.insert(name.clone(), v);
+    }
+    def.actions
+        .iter()
+        .map(|a| {
+            let a = substitute(a, &values)?;
+            serde_json::from_value(a).map_err(Into::into)
+        })
+        .collect()
+}
+
+fn substitute(v: &Value, args: &BTreeMap<String, Value>) -> Result<Value> {
+    Ok(match v {
+        Value::String(s) => {
+            if let Some(caps) = ARG_REGEX.captures(s).filter(|c| &c[0] == s.as_str()) {
+                lookup(args, &caps[1])?.clone()
+            } else {
+                let mut missing = None;
+                let out = ARG_REGEX.replace_all(s, |caps: &regex::Captures| {
+                    match lookup(args, &caps[1]) {
+                        Ok(Value::String(s)) => s.clone(),
+                        Ok(other) => other.to_string(),
+                        Err(e) => {
+                            missing.get_or_insert(e);
+                            String::new()
+                        }
+                    }
+                });
+                if let Some(e) = missing {
+                    return Err(e);
+                }
+                Value::String(out.into_owned())
+            }
+        }
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|i| substitute(i, args))
+                .collect::<Result<_>>()?,
+        ),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(k, v)| Ok((k.clone(), substitute(v, args)?)))
+                .collect::<Result<_>>()?,
+        ),
+        other => other.clone(),
+    })
+}
+
+fn lookup<'a>(args: &'a BTreeMap<String, Value>, name: &str) -> Result<&'a Value> {
+    args.get(name)
+        .ok_or_else(|| anyhow!("`{{arg.{}}}` is not a declared parameter", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const NOTES: &str = r#"
+new_note:
+  params:
+    title: { type: string }
+    count: { type: int, default: 2 }
+    login: { type: secret-ref }
+  actions:
+    - { act: launch, id: open, target: com.apple.Notes }
+    - act: repeat
+      times: "{arg.count}"
+      do:
+        - { act: type, text: "{arg.title} / {arg.login}" }
+wrapper:
+  actions:
+    - { act: call, id: note, macro: notes.new_note, args: { title: "t", login: "icloud" } }
+loop:
+  actions:
+    - { act: call, macro: notes.loop }
+"#;
+
+    fn setup() -> TempDir {
+        let tmp = TempDir::new().unwrap();
+        fs::write(tmp.path().join("notes.yaml"), NOTES).unwrap();
+        tmp
+    }
+
+    fn call(json: &str) -> Vec<Step> {
+        serde_json::from_str::<ActionList>(json).unwrap().actions
+    }
+
+    #[test]
+    fn test_expand_with_typed_args() {
+        let tmp = setup();
+        let acts = call(
+            r#"[{"act":"call","macro":"notes.new_note","args":{"title":"Hi","login":"icloud"}}]"#,
+        );
+        let out = expand_with(tmp.path(), acts, 0).unwrap();
+        assert_eq!(out.len(), 2);
+        match &out[1].act {
+            Action::Repeat { times, body, .. } => {
+                assert_eq!(*times, 2);
+                match &body[0].act {
+                    Action::Type { text, .. } => assert_eq!(text, "Hi / {secret.icloud}"),
+                    other => panic!("unexpected {:?}", other),
+                }
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_nested_calls_and_errors() {
+        let tmp = setup();
+        let out = expand_with(
+            tmp.path(),
+            call(
+                r#"[{"act":"call","macro":"notes.wrapper","retries":2,"description":"new note"}]"#,
+            ),
+            0,
+        )
+        .unwrap();
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[0].options.retries, Some(2));
+        assert_eq!(out[0].meta.id.as_deref(), Some("note.open"));
+        assert_eq!(out[1].meta.id, None);
+        assert_eq!(out[1].meta.description.as_deref(), Some("new note"));
+
+        let bad = [
+            r#"[{"act":"call","macro":"notes.loop"}]"#,
+            r#"[{"act":"call","macro":"notes.new_note","args":{"title":1,"login":"x"}}]"#,
+            r#"[{"act":"call","macro":"notes.new_note","args":{"title":"t"}}]"#,
+            r#"[{"act":"call","macro":"notes.new_note","args":{"title":"t","login":"x","y":1}}]"#,
+            r#"[{"act":"call","macro":"../notes.new_note"}]"#,
+        ];
+        for json in bad {
+            assert!(expand_with(tmp.path(), call(json), 0).is_err(), "{}", json);
+        }
+    }
+}
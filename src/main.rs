@@ -1,20 +1,29 @@
 #![allow(clippy::needless_return)]
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
+use std::path::{Path, PathBuf};
 
 mod action;
 pub mod adapter;
 mod api;
+mod dryrun;
+mod element;
 mod error;
 pub mod guard;
 mod job;
+mod keymap;
 mod mac_ax;
+mod macros;
 mod mask;
 mod models;
 mod policy;
+mod schema;
 mod screenshot;
+mod selector;
+mod store;
 mod tree;
 mod vault;
+mod window;
 #[derive(Parser)]
 #[command(author, version, about)]
 struct Cli {
@@ -36,6 +45,18 @@ enum Commands {
         #[arg(long, default_value_t = 8900)]
         port: u16,
     },
+    /// Run an action file (YAML or JSON) without the API server
+    Exec {
+        file: PathBuf,
+        /// validate and list the steps without touching the UI
+        #[arg(long)]
+        dry_run: bool,
+        /// write screenshot / snapshot artifacts into this directory
+        #[arg(long)]
+        artifacts: Option<PathBuf>,
+    },
+    /// Print the action JSON Schema and LLM tool definitions for the current policy
+    Schema,
 }
 
 #[tokio::main]
@@ -46,6 +67,78 @@ async fn main() -> Result<()> {
             let text = text.replace("{secret}", &secret_val);
             println!("⚠️  CLI モードは廃止されました。API サーバモードを使用してください。");
         }
+        Commands::Exec {
+            file,
+            dry_run,
+            artifacts,
+        } => {
+            if dry_run {
+                let report = dryrun::dry_run(load_action_file(&file)?, None);
+                for s in &report.steps {
+                    let label = s.meta.label(&s.step, s.act);
+                    let status = if s.errors.is_empty() {
+                        "ok"
+                    } else {
+                        "would fail"
+                    };
+                    println!("step {}: {} (dry run)", label, status);
+                    for note in &s.notes {
+                        println!("    {}", note);
+                    }
+                    for err in &s.errors {
+                        println!("    error: {}", err);
+                    }
+                }
+                if let Some(err) = &report.error {
+                    println!("error: {}", err);
+                }
+                if !report.ok {
+                    anyhow::bail!("dry run found problems");
+                }
+                return Ok(());
+            }
+            let list = job::prepare_actions(load_action_file(&file)?)?;
+            let mut ctx = job::JobContext {
+                job_id: Some(format!("exec-{}", uuid::Uuid::new_v4())),
+                ..job::JobContext::default()
+            };
+            let res = job::run_actions(list, &mut ctx);
+            for rec in &ctx.steps {
+                println!("{}", rec);
+            }
+            for (name, value) in &ctx.vars {
+                println!("var {} = {}", name, value);
+            }
+            for (n, art) in ctx.artifacts.iter().enumerate() {
+                let label = art
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| format!("step-{}", art.step));
+                let ext = art.extension();
+                match &artifacts {
+                    Some(dir) => {
+                        let path = dir.join(format!("{}-{}.{}", n, label, ext));
+                        std::fs::create_dir_all(dir)?;
+                        std::fs::write(&path, art.data.as_slice())?;
+                        println!("artifact {} ({}): {}", n, art.step, path.display());
+                    }
+                    None => println!(
+                        "artifact {} ({}): {} {} bytes",
+                        n,
+                        art.step,
+                        label,
+                        art.data.len()
+                    ),
+                }
+            }
+            if let Err(failure) = res {
+                anyhow::bail!("{}", failure);
+            }
+        }
+        Commands::Schema => {
+            let pol = policy::load()?;
+            println!("{}", serde_json::to_string_pretty(&schema::export(&pol))?);
+        }
         Commands::Serve { port } => {
             // guard エンジンをバックグラウンドで起動
             tokio::spawn(async {
@@ -66,3 +159,14 @@ async fn main() -> Result<()> {
     }
     Ok(())
 }
+
+/// `ActionList` from a `.yaml` / `.yml` file, JSON otherwise.
+fn load_action_file(path: &Path) -> Result<action::ActionList> {
+    let txt =
+        std::fs::read_to_string(path).with_context(|| format!("cannot read {}", path.display()))?;
+    let list = match path.extension().and_then(|e| e.to_str()) {
+        Some("yaml" | "yml") => serde_yaml::from_str(&txt)?,
+        _ => serde_json::from_str(&txt)?,
+    };
+    Ok(list)
+}
@@ -15,6 +15,18 @@ pub struct Policy {
     pub click_bounds: Option<Bounds>,
     pub allow_snapshot: bool,
     pub max_snapshot_per_min: Option<u32>,
+    pub max_repeat: Option<u32>,
+    pub max_retries: Option<u32>,
+    /// per `scroll` step, along each axis
+    pub max_scroll_px: Option<u32>,
+    /// bundle id globs that `quit_app` may not quit
+    pub protected_apps: Vec<String>,
+    /// host globs `open_url` may open; empty allows any host not denied
+    pub allowed_hosts: Vec<String>,
+    /// finished jobs are forgotten this long after they finish
+    pub max_job_age_hours: Option<u64>,
+    /// finished jobs beyond the newest `max_jobs` are forgotten
+    pub max_jobs: Option<usize>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -30,17 +42,52 @@ impl Default for Policy {
         Self {
             allow_acts: vec![
                 "launch".into(),
+                "open_url".into(),
                 "type".into(),
                 "wait".into(),
+                "wait_for".into(),
                 "click".into(),
+                "double_click".into(),
+                "right_click".into(),
+                "move_to".into(),
+                "drag".into(),
                 "scroll".into(),
                 "keypress".into(),
+                "read".into(),
+                "assert".into(),
+                "screenshot".into(),
+                "snapshot".into(),
+                "clipboard_set".into(),
+                "clipboard_get".into(),
+                "paste".into(),
+                "press".into(),
+                "set_value".into(),
+                "menu".into(),
+                "focus_window".into(),
+                "move_window".into(),
+                "resize_window".into(),
+                "minimize".into(),
+                "close_window".into(),
+                "quit_app".into(),
+                "if".into(),
+                "repeat".into(),
             ],
             denied_targets: vec![],
             max_wait_ms: Some(30_000),
             click_bounds: None,
             allow_snapshot: true,
             max_snapshot_per_min: Some(10),
+            max_repeat: Some(100),
+            max_retries: Some(5),
+            max_scroll_px: Some(20_000),
+            protected_apps: vec![
+                "com.apple.finder".into(),
+                "com.apple.dock".into(),
+                "com.apple.loginwindow".into(),
+            ],
+            allowed_hosts: vec![],
+            max_job_age_hours: Some(24 * 7),
+            max_jobs: Some(1000),
         }
     }
 }
@@ -64,73 +111,723 @@ pub(crate) fn load() -> Result<std::sync::Arc<Policy>> {
         .map(|lock| std::sync::Arc::new(lock.read().unwrap().clone()))
 }
 
-use crate::action::{Action, ActionList};
+use crate::action::{
+    menu_path, step_id, Action, ActionList, Condition, Point, ScrollUnit, Step, StepOptions,
+    WaitState,
+};
+use crate::keymap;
+use crate::selector::Selector;
+use globset::GlobSet;
+use once_cell::sync::Lazy;
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+static VAR_NAME: Lazy<regex::Regex> = Lazy::new(|| regex::Regex::new(r"^[a-zA-Z0-9_-]+$").unwrap());
+/// like `VAR_NAME`, plus the `.` of ids prefixed by a macro `call`
+static STEP_ID: Lazy<regex::Regex> = Lazy::new(|| regex::Regex::new(r"^[a-zA-Z0-9_.-]+$").unwrap());
 
-pub fn validate_actions(actions: &ActionList) -> Result<()> {
+pub fn validate_actions(list: &ActionList) -> Result<()> {
     let pol = load()?;
+    validate_options(&pol, &list.defaults)?;
+    validate_with(&pol, &list.actions)
+}
+
+fn validate_with(pol: &Policy, actions: &[Step]) -> Result<()> {
+    let sets = GlobSets {
+        denied: glob_set(&pol.denied_targets)?,
+        protected: glob_set(&pol.protected_apps)?,
+        allowed_hosts: glob_set(&pol.allowed_hosts)?,
+    };
+    check_unique_ids(actions, &mut HashSet::new())?;
+    validate_list(pol, &sets, actions, "", 1)
+}
+
+struct GlobSets {
+    denied: GlobSet,
+    protected: GlobSet,
+    allowed_hosts: GlobSet,
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet> {
     let mut gb = GlobSetBuilder::new();
-    for pat in &pol.denied_targets {
+    for pat in patterns {
         gb.add(Glob::new(pat)?);
     }
-    let denied_set = gb.build()?;
+    Ok(gb.build()?)
+}
 
-    for act in &actions.0 {
-        let name = act_name(act);
-        if !pol.allow_acts.iter().any(|s| s == name) {
-            return Err(anyhow!("policy_violation: act `{}` not allowed", name));
+/// Checks every action, recursing into `if` / `repeat` blocks so that all
+/// branches are validated up front. Errors name the offending step. `runs` is
+/// how often the enclosing `repeat` blocks run `actions`, capped by
+/// `max_repeat` as a whole.
+fn validate_list(
+    pol: &Policy,
+    sets: &GlobSets,
+    actions: &[Step],
+    prefix: &str,
+    runs: u64,
+) -> Result<()> {
+    for (i, step) in actions.iter().enumerate() {
+        let number = step_id(prefix, i);
+        check_step(pol, sets, step).map_err(|e| {
+            anyhow!(
+                "step {}: {}",
+                step.meta.label(&number, act_name(&step.act)),
+                e
+            )
+        })?;
+        match &step.act {
+            Action::If {
+                then, otherwise, ..
+            } => {
+                validate_list(pol, sets, then, &number, runs)?;
+                validate_list(pol, sets, otherwise, &number, runs)?;
+            }
+            Action::Repeat { times, body, .. } => {
+                let runs = runs.saturating_mul(u64::from(*times));
+                if let Some(max) = pol.max_repeat {
+                    if runs > u64::from(max) {
+                        return Err(anyhow!(
+                            "step {}: policy_violation: nested repeats run {} times, exceeding {}",
+                            step.meta.label(&number, act_name(&step.act)),
+                            runs,
+                            max
+                        ));
+                    }
+                }
+                validate_list(pol, sets, body, &number, runs)?
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Step ids must be unique across the whole list, nested blocks included.
+fn check_unique_ids<'a>(actions: &'a [Step], seen: &mut HashSet<&'a str>) -> Result<()> {
+    for step in actions {
+        if let Some(id) = &step.meta.id {
+            if !STEP_ID.is_match(id) {
+                return Err(anyhow!("invalid step id `{}`", id));
+            }
+            if !seen.insert(id) {
+                return Err(anyhow!("duplicate step id `{}`", id));
+            }
         }
-        if let Some(t) = act_target(act) {
-            if denied_set.is_match(t) {
-                return Err(anyhow!("policy_violation: target `{}` denied", t));
+        match &step.act {
+            Action::If {
+                then, otherwise, ..
+            } => {
+                check_unique_ids(then, seen)?;
+                check_unique_ids(otherwise, seen)?;
             }
+            Action::Repeat { body, .. } => check_unique_ids(body, seen)?,
+            _ => {}
         }
-        if let Action::Wait { ms } = act {
-            if let Some(max) = pol.max_wait_ms {
-                if *ms > max {
-                    return Err(anyhow!("policy_violation: wait {}ms exceeds {}", ms, max));
-                }
+    }
+    Ok(())
+}
+
+/// Checks one step on its own; blocks' bodies are handled by `validate_list`.
+fn check_step(pol: &Policy, sets: &GlobSets, step: &Step) -> Result<()> {
+    let act = &step.act;
+    validate_options(pol, &step.options)?;
+    let name = act_name(act);
+    if !pol.allow_acts.iter().any(|s| s == name) {
+        return Err(anyhow!("policy_violation: act `{}` not allowed", name));
+    }
+    for t in act_targets(act) {
+        if sets.denied.is_match(t) {
+            return Err(anyhow!("policy_violation: target `{}` denied", t));
+        }
+    }
+    if let Action::Wait { ms } = act {
+        if let Some(max) = pol.max_wait_ms {
+            if *ms > max {
+                return Err(anyhow!("policy_violation: wait {}ms exceeds {}", ms, max));
+            }
+        }
+    }
+    if let Action::Drag { duration_ms, .. } = act {
+        if let Some(max) = pol.max_wait_ms {
+            if *duration_ms > max {
+                return Err(anyhow!(
+                    "policy_violation: drag duration {}ms exceeds {}",
+                    duration_ms,
+                    max
+                ));
             }
         }
-        if let (
-            Some(b),
-            Action::Click {
+    }
+    // its timeout is `timeout_ms`, capped in `validate_options`
+    if let Action::WaitFor { cond } = act {
+        validate_condition(cond)?;
+        if cond.selector.is_none() {
+            return Err(anyhow!("wait_for requires `selector`"));
+        }
+    }
+    if let Action::Assert {
+        selector,
+        title,
+        equals,
+        matches,
+        ..
+    } = act
+    {
+        if selector.is_none() && title.is_none() {
+            return Err(anyhow!("assert requires `selector` or `title`"));
+        }
+        if selector.is_none() && (equals.is_some() || matches.is_some()) {
+            return Err(anyhow!("assert `equals` / `matches` require `selector`"));
+        }
+        if let Some(sel) = selector {
+            Selector::parse(sel)?;
+        }
+        if let Some(glob) = title {
+            Glob::new(glob)?;
+        }
+        if let Some(re) = matches {
+            regex::Regex::new(re)?;
+        }
+    }
+    if let Action::Read { selector, .. }
+    | Action::Press { selector, .. }
+    | Action::SetValue { selector, .. } = act
+    {
+        Selector::parse(selector)?;
+    }
+    if let Action::Menu { path } = act {
+        for item in menu_path(path) {
+            if item.is_empty() {
+                return Err(anyhow!("menu path `{}` has an empty item", path));
+            }
+            Glob::new(item)?;
+        }
+    }
+    if let (Action::Scroll { dx, dy, unit, .. }, Some(max)) = (act, pol.max_scroll_px) {
+        let px = dx.unsigned_abs().max(dy.unsigned_abs()) as u64 * scroll_unit_px(*unit);
+        if px > max as u64 {
+            return Err(anyhow!(
+                "policy_violation: scroll of ~{}px exceeds {}",
+                px,
+                max
+            ));
+        }
+    }
+    if let Action::Keypress { key, repeat } = act {
+        keymap::parse_keys(key)?;
+        if let (Some(n), Some(max)) = (repeat, pol.max_repeat) {
+            if *n > max {
+                return Err(anyhow!(
+                    "policy_violation: keypress repeat {} exceeds {}",
+                    n,
+                    max
+                ));
+            }
+        }
+    }
+    if let Action::OpenUrl { url, .. } = act {
+        let host = url_host(url)?;
+        if sets.denied.is_match(&host) {
+            return Err(anyhow!("policy_violation: host `{}` denied", host));
+        }
+        if !pol.allowed_hosts.is_empty() && !sets.allowed_hosts.is_match(&host) {
+            return Err(anyhow!(
+                "policy_violation: host `{}` not in allowed_hosts",
+                host
+            ));
+        }
+    }
+    if let Action::QuitApp { target } = act {
+        if sets.protected.is_match(target) {
+            return Err(anyhow!("policy_violation: `{}` is a protected app", target));
+        }
+    }
+    if let Action::ResizeWindow { width, height, .. } = act {
+        if *width == 0 || *height == 0 {
+            return Err(anyhow!("resize_window needs a non-zero width and height"));
+        }
+    }
+    if let Action::Snapshot { .. } = act {
+        if !pol.allow_snapshot {
+            return Err(anyhow!("policy_violation: snapshot disabled"));
+        }
+    }
+    if let Action::Screenshot { name: Some(n) } | Action::Snapshot { name: Some(n), .. } = act {
+        if !VAR_NAME.is_match(n) {
+            return Err(anyhow!("{}: invalid artifact name `{}`", name, n));
+        }
+    }
+    if let Action::Read { into, .. } | Action::ClipboardGet { into } = act {
+        if !VAR_NAME.is_match(into) {
+            return Err(anyhow!("{}: invalid variable name `{}`", name, into));
+        }
+    }
+    for p in act_points(act) {
+        match p {
+            Point {
+                selector: Some(sel),
+                ..
+            } => {
+                Selector::parse(sel)?;
+            }
+            Point {
                 x: Some(px),
                 y: Some(py),
                 ..
-            },
-        ) = (pol.click_bounds.as_ref(), act)
-        {
-            if *px < b.x_min || *px > b.x_max || *py < b.y_min || *py > b.y_max {
+            } => check_bounds(pol, *px, *py)?,
+            // the missing coordinate would default at run time, unchecked
+            Point {
+                x: Some(_),
+                y: None,
+                ..
+            }
+            | Point {
+                x: None,
+                y: Some(_),
+                ..
+            } => return Err(anyhow!("a point needs both `x` and `y`, or a `selector`")),
+            // only scroll has a default spot: wherever the pointer is
+            _ if !matches!(act, Action::Scroll { .. }) => {
                 return Err(anyhow!(
-                    "policy_violation: click ({},{}) out of bounds",
-                    px,
-                    py
-                ));
+                    "{}: a point needs both `x` and `y`, or a `selector`",
+                    name
+                ))
+            }
+            _ => {}
+        }
+    }
+    match act {
+        Action::If { cond, .. } => validate_condition(cond)?,
+        Action::Repeat { times, cond, .. } => {
+            if let Some(max) = pol.max_repeat {
+                if *times > max {
+                    return Err(anyhow!(
+                        "policy_violation: repeat {} times exceeds {}",
+                        times,
+                        max
+                    ));
+                }
+            }
+            if let Some(cond) = cond {
+                validate_condition(cond)?;
             }
         }
+        _ => {}
     }
     Ok(())
 }
 
-fn act_name(a: &Action) -> &'static str {
+/// Lower-cased host of an `open_url` URL; only http(s) URLs with a host pass.
+fn url_host(url: &str) -> Result<String> {
+    let uri: axum::http::Uri = url
+        .parse()
+        .map_err(|e| anyhow!("open_url: invalid url `{}`: {}", url, e))?;
+    match uri.scheme_str() {
+        Some("http") | Some("https") => {}
+        _ => {
+            return Err(anyhow!(
+                "open_url: only http(s) urls are allowed, got `{}`",
+                url
+            ))
+        }
+    }
+    uri.host()
+        .filter(|h| !h.is_empty())
+        .map(|h| h.trim_end_matches('.').to_ascii_lowercase())
+        .ok_or_else(|| anyhow!("open_url: `{}` has no host", url))
+}
+
+/// Retry counts are capped by `max_retries`, timeouts and delays by `max_wait_ms`.
+fn validate_options(pol: &Policy, opts: &StepOptions) -> Result<()> {
+    if let (Some(n), Some(max)) = (opts.retries, pol.max_retries) {
+        if n > max {
+            return Err(anyhow!("policy_violation: {} retries exceeds {}", n, max));
+        }
+    }
+    if let Some(max) = pol.max_wait_ms {
+        for ms in [opts.timeout_ms, opts.retry_delay_ms].into_iter().flatten() {
+            if ms > max {
+                return Err(anyhow!("policy_violation: {}ms exceeds {}", ms, max));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn validate_condition(cond: &Condition) -> Result<()> {
+    match (&cond.selector, &cond.var) {
+        (Some(sel), None) => {
+            Selector::parse(sel)?;
+        }
+        (None, Some(_)) if cond.state == WaitState::Enabled => {
+            return Err(anyhow!("`enabled` cannot be checked on a variable"));
+        }
+        (None, Some(_)) => {}
+        _ => {
+            return Err(anyhow!(
+                "condition needs exactly one of `selector` or `var`"
+            ))
+        }
+    }
+    match (cond.state, &cond.value) {
+        (WaitState::ValueMatches, None) => Err(anyhow!("`value_matches` requires `value`")),
+        (_, Some(re)) => {
+            regex::Regex::new(re)?;
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Check a concrete screen point against `click_bounds`.
+/// Used for coordinates only known at run time (e.g. resolved selectors).
+pub fn check_point(x: i32, y: i32) -> Result<()> {
+    let pol = load()?;
+    check_bounds(&pol, x, y)
+}
+
+/// Snapshots taken in the current minute, across `/snapshot` and `snapshot` steps.
+static SNAPSHOTS: Lazy<Mutex<(Instant, u32)>> = Lazy::new(|| Mutex::new((Instant::now(), 0)));
+
+/// Take one of the `max_snapshot_per_min` slots, or fail with `rate limit`.
+pub fn take_snapshot_slot() -> Result<()> {
+    let pol = load()?;
+    if !take_slot(&SNAPSHOTS, pol.max_snapshot_per_min.unwrap_or(10)) {
+        return Err(anyhow!("rate limit"));
+    }
+    Ok(())
+}
+
+fn take_slot(window: &Mutex<(Instant, u32)>, max: u32) -> bool {
+    let mut guard = window.lock().unwrap();
+    let (started, count) = &mut *guard;
+    if started.elapsed() > Duration::from_secs(60) {
+        *started = Instant::now();
+        *count = 0;
+    }
+    if *count >= max {
+        return false;
+    }
+    *count += 1;
+    true
+}
+
+fn check_bounds(pol: &Policy, x: i32, y: i32) -> Result<()> {
+    if let Some(b) = pol.click_bounds.as_ref() {
+        if x < b.x_min || x > b.x_max || y < b.y_min || y > b.y_max {
+            return Err(anyhow!(
+                "policy_violation: click ({},{}) out of bounds",
+                x,
+                y
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn act_name(a: &Action) -> &'static str {
     match a {
         Action::Launch { .. } => "launch",
+        Action::OpenUrl { .. } => "open_url",
         Action::Type { .. } => "type",
         Action::Wait { .. } => "wait",
+        Action::WaitFor { .. } => "wait_for",
         Action::Click { .. } => "click",
+        Action::DoubleClick { .. } => "double_click",
+        Action::RightClick { .. } => "right_click",
+        Action::MoveTo { .. } => "move_to",
+        Action::Drag { .. } => "drag",
         Action::Scroll { .. } => "scroll",
         Action::Keypress { .. } => "keypress",
+        Action::Read { .. } => "read",
+        Action::Assert { .. } => "assert",
+        Action::Screenshot { .. } => "screenshot",
+        Action::Snapshot { .. } => "snapshot",
+        Action::ClipboardSet { .. } => "clipboard_set",
+        Action::ClipboardGet { .. } => "clipboard_get",
+        Action::Paste => "paste",
+        Action::Press { .. } => "press",
+        Action::SetValue { .. } => "set_value",
+        Action::Menu { .. } => "menu",
+        Action::FocusWindow { .. } => "focus_window",
+        Action::MoveWindow { .. } => "move_window",
+        Action::ResizeWindow { .. } => "resize_window",
+        Action::Minimize { .. } => "minimize",
+        Action::CloseWindow { .. } => "close_window",
+        Action::QuitApp { .. } => "quit_app",
+        Action::If { .. } => "if",
+        Action::Repeat { .. } => "repeat",
+        Action::Call { .. } => "call",
         Action::Unsupported => "unsupported",
     }
 }
 
-fn act_target(a: &Action) -> Option<&str> {
+/// Pixel estimate of one scroll unit for `max_scroll_px`; pages are
+/// counted as a full display height.
+fn scroll_unit_px(unit: ScrollUnit) -> u64 {
+    match unit {
+        ScrollUnit::Pixel => 1,
+        ScrollUnit::Line => 10,
+        ScrollUnit::Page => 1_600,
+    }
+}
+
+/// Every pointer position an action touches (both ends of a drag).
+fn act_points(a: &Action) -> Vec<&Point> {
+    match a {
+        Action::Click { at, .. }
+        | Action::DoubleClick { at, .. }
+        | Action::RightClick { at, .. }
+        | Action::MoveTo { at }
+        | Action::Scroll { at, .. } => vec![at],
+        Action::Drag { from, to, .. } => vec![from, to],
+        _ => vec![],
+    }
+}
+
+fn act_targets(a: &Action) -> Vec<&str> {
     match a {
-        Action::Launch { target } => Some(target),
-        Action::Click {
-            selector: Some(sel),
+        Action::Launch { target }
+        | Action::OpenUrl {
+            browser: Some(target),
             ..
-        } => Some(sel),
-        _ => None,
+        } => vec![target],
+        Action::Press { selector, .. } | Action::SetValue { selector, .. } => vec![selector],
+        Action::Menu { path } => vec![path],
+        _ => act_points(a)
+            .into_iter()
+            .filter_map(|p| p.selector.as_deref())
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> Vec<Step> {
+        serde_json::from_str::<ActionList>(json).unwrap().actions
+    }
+
+    #[test]
+    fn test_snapshot_slots_run_out_within_a_minute() {
+        let window = Mutex::new((Instant::now(), 0));
+        assert!(take_slot(&window, 2));
+        assert!(take_slot(&window, 2));
+        assert!(!take_slot(&window, 2));
+        window.lock().unwrap().0 = Instant::now() - Duration::from_secs(61);
+        assert!(take_slot(&window, 2));
+    }
+
+    #[test]
+    fn test_nested_branches_are_checked() {
+        let pol = Policy {
+            denied_targets: vec!["com.malware.*".into()],
+            ..Policy::default()
+        };
+        let acts = parse(
+            r#"[{"act":"if","cond":{"selector":"AXSheet"},
+                 "then":[{"act":"keypress","key":"enter"}],
+                 "else":[{"act":"repeat","times":2,"do":[
+                     {"act":"launch","target":"com.malware.app"}]}]}]"#,
+        );
+        let err = validate_with(&pol, &acts).unwrap_err();
+        assert!(err.to_string().contains("com.malware.app"));
+    }
+
+    #[test]
+    fn test_drag_bounds_checked_at_both_ends() {
+        let pol = Policy {
+            click_bounds: Some(Bounds {
+                x_min: 0,
+                x_max: 100,
+                y_min: 0,
+                y_max: 100,
+            }),
+            ..Policy::default()
+        };
+        let acts = parse(
+            r#"[{"act":"drag","from":{"x":10,"y":10},"to":{"x":500,"y":10},"duration_ms":200}]"#,
+        );
+        assert!(validate_with(&pol, &acts).is_err());
+
+        let acts = parse(r#"[{"act":"double_click","x":50,"y":50,"modifiers":["cmd","shift"]}]"#);
+        assert!(validate_with(&pol, &acts).is_ok());
+        let acts = parse(r#"[{"act":"scroll","dy":300}]"#);
+        assert!(validate_with(&pol, &acts).is_ok());
+
+        // a lone coordinate would slip past the bounds check
+        for json in [
+            r#"[{"act":"click","x":5000}]"#,
+            r#"[{"act":"move_to","y":5000}]"#,
+            r#"[{"act":"drag","from":{"x":10,"y":10},"to":{"x":50}}]"#,
+            r#"[{"act":"click"}]"#,
+            r#"[{"act":"drag","from":{},"to":{"x":50,"y":50}}]"#,
+        ] {
+            let err = validate_with(&pol, &parse(json)).unwrap_err();
+            assert!(err.to_string().contains("both `x` and `y`"), "{}", json);
+        }
+
+        let acts = parse(
+            r#"[{"act":"drag","from":{"x":10,"y":10},"to":{"x":50,"y":50},"duration_ms":3600000}]"#,
+        );
+        let err = validate_with(&pol, &acts).unwrap_err();
+        assert!(err.to_string().contains("drag duration"));
+    }
+
+    #[test]
+    fn test_repeat_cap_and_condition() {
+        let pol = Policy::default();
+        let acts = parse(r#"[{"act":"repeat","times":1000,"do":[]}]"#);
+        assert!(validate_with(&pol, &acts).is_err());
+
+        // 100 × 100 passes each block's cap but not the total
+        let acts = parse(
+            r#"[{"act":"repeat","times":100,"do":[
+                 {"act":"repeat","times":100,"do":[{"act":"wait","ms":1}]}]}]"#,
+        );
+        let err = validate_with(&pol, &acts).unwrap_err();
+        assert!(err.to_string().contains("nested repeats run 10000 times"));
+        let acts = parse(
+            r#"[{"act":"repeat","times":10,"do":[
+                 {"act":"repeat","times":10,"do":[{"act":"wait","ms":1}]}]}]"#,
+        );
+        assert!(validate_with(&pol, &acts).is_ok());
+
+        let acts = parse(
+            r#"[{"act":"repeat","times":3,"while":{"selector":"AXButton","state":"value_matches"},"do":[]}]"#,
+        );
+        assert!(validate_with(&pol, &acts).is_err());
+
+        let acts = parse(
+            r#"[{"act":"repeat","times":3,"while":{"selector":"AXButton","state":"enabled"},
+                 "do":[{"act":"click","x":10,"y":10},
+                       {"act":"wait_for","selector":"AXSheet","state":"gone","timeout_ms":500}]}]"#,
+        );
+        assert!(validate_with(&pol, &acts).is_ok());
+    }
+
+    #[test]
+    fn test_retry_options_capped() {
+        let pol = Policy::default();
+        let acts = parse(r#"[{"act":"click","x":1,"y":1,"retries":50}]"#);
+        assert!(validate_with(&pol, &acts).is_err());
+
+        let acts = parse(
+            r#"[{"act":"if","cond":{"var":"x"},"then":[
+                 {"act":"keypress","key":"enter","retries":1,"retry_delay_ms":600000}]}]"#,
+        );
+        assert!(validate_with(&pol, &acts).is_err());
+
+        let acts = parse(r#"[{"act":"click","x":1,"y":1,"retries":3,"retry_delay_ms":200}]"#);
+        assert!(validate_with(&pol, &acts).is_ok());
+    }
+
+    #[test]
+    fn test_scroll_cap_and_target() {
+        let pol = Policy::default();
+        let acts = parse(r#"[{"act":"scroll","dy":3,"unit":"page","selector":"AXScrollArea"}]"#);
+        assert!(validate_with(&pol, &acts).is_ok());
+
+        let acts = parse(r#"[{"act":"scroll","dx":-50,"unit":"page"}]"#);
+        assert!(validate_with(&pol, &acts).is_err());
+
+        let acts = parse(r#"[{"act":"scroll","dy":10,"selector":"AXRow["}]"#);
+        assert!(validate_with(&pol, &acts).is_err());
+    }
+
+    #[test]
+    fn test_protected_apps_cannot_be_quit() {
+        let pol = Policy {
+            protected_apps: vec!["com.apple.*".into()],
+            ..Policy::default()
+        };
+        let acts = parse(
+            r#"[{"act":"repeat","times":1,"do":[{"act":"quit_app","target":"com.apple.Safari"}]}]"#,
+        );
+        let err = validate_with(&pol, &acts).unwrap_err();
+        assert!(err.to_string().contains("protected"));
+
+        let acts = parse(
+            r#"[{"act":"focus_window","window":{"title":"Inbox*"}},
+                {"act":"resize_window","window":"front","width":800,"height":600},
+                {"act":"quit_app","target":"org.mozilla.firefox"}]"#,
+        );
+        assert!(validate_with(&pol, &acts).is_ok());
+    }
+
+    #[test]
+    fn test_menu_path_checked() {
+        let pol = Policy {
+            denied_targets: vec!["* > Quit*".into()],
+            ..Policy::default()
+        };
+        let ok = parse(r#"[{"act":"menu","path":"File > Export > PDF"}]"#);
+        assert!(validate_with(&pol, &ok).is_ok());
+
+        for json in [
+            r#"[{"act":"menu","path":"File >  > PDF"}]"#,
+            r#"[{"act":"menu","path":"Notes > Quit Notes"}]"#,
+            r#"[{"act":"press","selector":"AXButton["}]"#,
+        ] {
+            assert!(validate_with(&pol, &parse(json)).is_err(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn test_open_url_host_policy() {
+        let pol = Policy {
+            denied_targets: vec!["*.phishing.com".into(), "com.malware.*".into()],
+            ..Policy::default()
+        };
+        let ok = parse(r#"[{"act":"open_url","url":"https://example.com/login?next=/"}]"#);
+        assert!(validate_with(&pol, &ok).is_ok());
+
+        for json in [
+            r#"[{"act":"open_url","url":"https://login.PHISHING.com./x"}]"#,
+            r#"[{"act":"open_url","url":"https://example.com","browser":"com.malware.browser"}]"#,
+            r#"[{"act":"open_url","url":"file:///etc/passwd"}]"#,
+            r#"[{"act":"open_url","url":"example.com"}]"#,
+        ] {
+            assert!(validate_with(&pol, &parse(json)).is_err(), "{}", json);
+        }
+
+        let pol = Policy {
+            allowed_hosts: vec!["*.example.com".into()],
+            ..Policy::default()
+        };
+        let acts = parse(r#"[{"act":"open_url","url":"http://app.example.com:8080/"}]"#);
+        assert!(validate_with(&pol, &acts).is_ok());
+        let acts = parse(r#"[{"act":"open_url","url":"https://example.org/"}]"#);
+        assert!(validate_with(&pol, &acts).is_err());
+    }
+
+    #[test]
+    fn test_errors_name_the_step() {
+        let pol = Policy::default();
+        let acts = parse(
+            r#"[{"act":"wait","ms":1},
+                {"act":"repeat","times":1,"do":[
+                  {"act":"wait","ms":99999,"id":"settle","description":"let the sheet close"}]}]"#,
+        );
+        let err = validate_with(&pol, &acts).unwrap_err().to_string();
+        assert!(
+            err.starts_with("step `settle` (let the sheet close): policy_violation"),
+            "{}",
+            err
+        );
+
+        let acts = parse(r#"[{"act":"keypress","key":"F99"}]"#);
+        let err = validate_with(&pol, &acts).unwrap_err().to_string();
+        assert!(err.starts_with("step 0 (keypress): "), "{}", err);
+
+        for json in [
+            r#"[{"act":"wait","ms":1,"id":"a"},{"act":"if","cond":{"var":"x"},"then":[{"act":"wait","ms":1,"id":"a"}]}]"#,
+            r#"[{"act":"wait","ms":1,"id":"no spaces"}]"#,
+        ] {
+            assert!(validate_with(&pol, &parse(json)).is_err(), "{}", json);
+        }
     }
 }
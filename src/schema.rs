@@ -0,0 +1,113 @@
+// src/schema.rs
+
+use crate::action::ActionList;
+use crate::policy::Policy;
+use schemars::schema_for;
+use serde_json::{json, Value};
+
+const TOOL_NAME: &str = "run_actions";
+const TOOL_DESCRIPTION: &str = "Send a sequence of UI actions to Thin-SAG. \
+Use {secret.<label>} for credentials and {var.<name>} for values captured by read / clipboard_get.";
+
+/// JSON Schema of `ActionList` (the `{ defaults, actions }` form), generated
+/// from `action::Action` and reduced to
+/// the acts the policy allows (`call` is always kept: macros are expanded and
+/// validated step by step on the server).
+pub fn action_schema(pol: &Policy) -> Value {
+    let mut schema = serde_json::to_value(schema_for!(ActionList)).unwrap_or_default();
+    if let Some(Value::Array(variants)) = schema.pointer_mut("/definitions/Step/oneOf") {
+        variants.retain(|v| {
+            variant_act(v)
+                .is_some_and(|act| act == "call" || pol.allow_acts.iter().any(|a| a == act))
+        });
+    }
+    schema
+}
+
+/// `act` tag of one `oneOf` entry of the internally tagged `Action` enum.
+fn variant_act(v: &Value) -> Option<&str> {
+    v.pointer("/properties/act/enum/0").and_then(Value::as_str)
+}
+
+/// Tool input: the `ActionList` object itself, so tool call arguments can be
+/// posted to `/run-json` unchanged.
+fn tool_parameters(pol: &Policy) -> Value {
+    let mut schema = action_schema(pol);
+    if let Some(obj) = schema.as_object_mut() {
+        obj.remove("$schema");
+        obj.remove("title");
+    }
+    schema
+}
+
+pub fn openai_tool(pol: &Policy) -> Value {
+    json!({
+        "type": "function",
+        "function": {
+            "name": TOOL_NAME,
+            "description": TOOL_DESCRIPTION,
+            "parameters": tool_parameters(pol),
+        }
+    })
+}
+
+pub fn anthropic_tool(pol: &Policy) -> Value {
+    json!({
+        "name": TOOL_NAME,
+        "description": TOOL_DESCRIPTION,
+        "input_schema": tool_parameters(pol),
+    })
+}
+
+/// Body of `GET /schema/actions` and output of `thin-sag schema`.
+pub fn export(pol: &Policy) -> Value {
+    json!({
+        "schema": action_schema(pol),
+        "openai": [openai_tool(pol)],
+        "anthropic": [anthropic_tool(pol)],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn acts(schema: &Value) -> Vec<String> {
+        schema["definitions"]["Step"]["oneOf"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|v| variant_act(v).map(String::from))
+            .collect()
+    }
+
+    #[test]
+    fn test_schema_follows_allow_acts() {
+        let all = acts(&action_schema(&Policy::default()));
+        assert!(all.contains(&"scroll".to_string()));
+        assert!(all.contains(&"keypress".to_string()));
+        assert!(!all.contains(&"Unsupported".to_string()));
+
+        let pol = Policy {
+            allow_acts: vec!["launch".into(), "type".into()],
+            ..Policy::default()
+        };
+        assert_eq!(acts(&action_schema(&pol)), ["launch", "type", "call"]);
+    }
+
+    #[test]
+    fn test_tool_definitions() {
+        let pol = Policy::default();
+        let openai = openai_tool(&pol);
+        let params = &openai["function"]["parameters"];
+        assert_eq!(
+            params["properties"]["actions"]["items"]["$ref"],
+            "#/definitions/Step"
+        );
+        assert!(params["definitions"]["Point"].is_object());
+
+        let anthropic = anthropic_tool(&pol);
+        assert_eq!(anthropic["name"], TOOL_NAME);
+        assert_eq!(&anthropic["input_schema"], params);
+    }
+}
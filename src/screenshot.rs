@@ -23,9 +23,8 @@ struct Base64Response {
     data: String,
 }
 
-pub async fn screenshot_handler(
-    Query(params): Query<ScreenshotParams>,
-) -> Result<impl IntoResponse, ApiError> {
+/// Capture the whole desktop as PNG bytes via `screencapture`.
+pub fn capture_png() -> anyhow::Result<Vec<u8>> {
     let tmp_path = std::env::temp_dir().join(format!("thin_sag_screenshot_{}.png", Uuid::new_v4()));
     eprintln!("DEBUG: tmp_path = {:?}", tmp_path);
 
@@ -33,24 +32,31 @@ pub async fn screenshot_handler(
         .arg("-x")
         .arg(&tmp_path)
         .output()
-        .map_err(|e| ApiError::Internal(anyhow!("failed to spawn screencapture: {}", e)))?;
+        .map_err(|e| anyhow!("failed to spawn screencapture: {}", e))?;
     eprintln!("DEBUG: status = {:?}", output.status);
     if !output.status.success() {
         let code = output.status.code();
         let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(ApiError::Internal(anyhow!(
+        return Err(anyhow!(
             "screencapture failed (code {:?}): {}",
             code,
             stderr
-        )));
+        ));
     }
 
-    let image_data = fs::read(&tmp_path)
-        .map_err(|e| ApiError::Internal(anyhow!("failed to read screenshot: {}", e)))?;
+    let image_data =
+        fs::read(&tmp_path).map_err(|e| anyhow!("failed to read screenshot: {}", e))?;
 
     if let Err(e) = fs::remove_file(&tmp_path) {
         eprintln!("WARNING: remove_file {:?}: {}", tmp_path, e);
     }
+    Ok(image_data)
+}
+
+pub async fn screenshot_handler(
+    Query(params): Query<ScreenshotParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let image_data = capture_png().map_err(ApiError::Internal)?;
 
     if let Some(dest) = params.output {
         fs::write(&dest, &image_data)
@@ -0,0 +1,468 @@
+// src/selector.rs
+
+use crate::tree::{element_at, snapshot_tree, Rect, UiNode, WindowSelector};
+use anyhow::{anyhow, bail, Result};
+use globset::{Glob, GlobMatcher};
+use objc::runtime::Object;
+use regex::Regex;
+
+/// Element selector evaluated against a `UiNode` snapshot.
+///
+/// CSS-like grammar:
+///
+/// ```text
+/// AXWindow > AXGroup AXButton[label="Save*"]:nth-child(2)
+/// ```
+///
+/// - `AXButton` matches the role, `*` (or nothing) matches any role
+/// - `[attr="glob"]` glob match, `[attr~="regex"]` regex match;
+///   attr is one of `role`, `label` (alias `title`) or `value`
+/// - `:nth-child(N)` 1-based position among the parent's children
+/// - `A > B` B is a direct child of A, `A B` B is any descendant of A
+#[derive(Debug)]
+pub struct Selector {
+    src: String,
+    steps: Vec<(Combinator, Step)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Combinator {
+    Descendant,
+    Child,
+}
+
+#[derive(Debug, Default)]
+struct Step {
+    role: Option<String>,
+    attrs: Vec<(Attr, Pattern)>,
+    nth_child: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Attr {
+    Role,
+    Label,
+    Value,
+}
+
+#[derive(Debug)]
+enum Pattern {
+    Glob(GlobMatcher),
+    Regex(Regex),
+}
+
+impl Pattern {
+    fn is_match(&self, s: &str) -> bool {
+        match self {
+            Pattern::Glob(g) => g.is_match(s),
+            Pattern::Regex(r) => r.is_match(s),
+        }
+    }
+}
+
+impl Step {
+    fn matches(&self, node: &UiNode, nth: usize) -> bool {
+        if let Some(role) = &self.role {
+            if node.role != *role {
+                return false;
+            }
+        }
+        if let Some(n) = self.nth_child {
+            if n != nth {
+                return false;
+            }
+        }
+        self.attrs.iter().all(|(attr, pat)| match attr {
+            Attr::Role => pat.is_match(&node.role),
+            Attr::Label => pat.is_match(&node.label),
+            Attr::Value => node
+                .value
+                .as_deref()
+                .map(|v| pat.is_match(v))
+                .unwrap_or(false),
+        })
+    }
+}
+
+/// (node, 1-based position among siblings)
+type Entry<'a> = (&'a UiNode, usize);
+
+impl Selector {
+    pub fn parse(src: &str) -> Result<Self> {
+        let mut p = Parser {
+            chars: src.chars().collect(),
+            pos: 0,
+        };
+        let mut steps = Vec::new();
+        let mut comb = Combinator::Descendant;
+        loop {
+            p.skip_ws();
+            if p.eof() {
+                break;
+            }
+            if p.peek() == Some('>') {
+                if steps.is_empty() || comb == Combinator::Child {
+                    bail!("selector `{}`: unexpected `>` at {}", src, p.pos);
+                }
+                p.pos += 1;
+                comb = Combinator::Child;
+                continue;
+            }
+            let step = p.step().map_err(|e| anyhow!("selector `{}`: {}", src, e))?;
+            steps.push((comb, step));
+            comb = Combinator::Descendant;
+        }
+        if steps.is_empty() {
+            bail!("selector is empty");
+        }
+        if comb == Combinator::Child {
+            bail!("selector `{}`: dangling `>`", src);
+        }
+        Ok(Self {
+            src: src.to_string(),
+            steps,
+        })
+    }
+
+    /// All matching nodes in document order, plus the number of nodes visited.
+    pub fn find_all<'a>(&self, root: &'a UiNode) -> (Vec<&'a UiNode>, usize) {
+        let mut hits = Vec::new();
+        let mut total = 0;
+        let mut chain = vec![(root, 1)];
+        self.walk(&mut chain, &mut hits, &mut total);
+        (hits, total)
+    }
+
+    /// Exactly one matching node, or a "matched n/N elements" error.
+    pub fn find_one<'a>(&self, root: &'a UiNode) -> Result<&'a UiNode> {
+        let (hits, total) = self.find_all(root);
+        if hits.len() == 1 {
+            Ok(hits[0])
+        } else {
+            Err(anyhow!(
+                "selector `{}` matched {}/{} elements",
+                self.src,
+                hits.len(),
+                total
+            ))
+        }
+    }
+
+    fn walk<'a>(&self, chain: &mut Vec<Entry<'a>>, hits: &mut Vec<&'a UiNode>, total: &mut usize) {
+        *total += 1;
+        let node = chain[chain.len() - 1].0;
+        if self.match_at(chain, self.steps.len() - 1) {
+            hits.push(node);
+        }
+        for (i, child) in node.children.iter().enumerate() {
+            chain.push((child, i + 1));
+            self.walk(chain, hits, total);
+            chain.pop();
+        }
+    }
+
+    /// Does the last node of `chain` match step `si` (with its ancestors
+    /// satisfying the steps before it)?
+    fn match_at(&self, chain: &[Entry], si: usize) -> bool {
+        let (node, nth) = chain[chain.len() - 1];
+        let (comb, step) = &self.steps[si];
+        if !step.matches(node, nth) {
+            return false;
+        }
+        if si == 0 {
+            return true;
+        }
+        let parents = &chain[..chain.len() - 1];
+        match comb {
+            Combinator::Child => !parents.is_empty() && self.match_at(parents, si - 1),
+            Combinator::Descendant => {
+                (1..=parents.len()).any(|end| self.match_at(&parents[..end], si - 1))
+            }
+        }
+    }
+}
+
+/// Snapshot the front window and return the frame of the element matched
+/// by `selector`.
+pub fn resolve_rect(selector: &str) -> Result<Rect> {
+    let sel = Selector::parse(selector)?;
+    let tree = snapshot_tree(WindowSelector::Front)?;
+    let node = sel.find_one(&tree)?;
+    node.rect
+        .clone()
+        .ok_or_else(|| anyhow!("selector `{}` matched an element without a frame", selector))
+}
+
+/// Live AX element matched by `selector` in `window`, for acts that talk to
+/// the element instead of synthesizing input.
+pub(crate) fn resolve_element(selector: &str, window: &WindowSelector) -> Result<*mut Object> {
+    let sel = Selector::parse(selector)?;
+    let tree = snapshot_tree(window.clone())?;
+    let node = sel.find_one(&tree)?;
+    let path = tree
+        .path_to(node)
+        .ok_or_else(|| anyhow!("selector `{}`: matched node not in tree", selector))?;
+    element_at(window, &path)
+}
+
+/// Screen point at the center of the element matched by `selector`,
+/// checked against `click_bounds`, and the element's frame.
+pub fn resolve_target(selector: &str) -> Result<((i32, i32), Rect)> {
+    let rect = resolve_rect(selector)?;
+    let (x, y) = rect.center();
+    let (x, y) = (x.round() as i32, y.round() as i32);
+    crate::policy::check_point(x, y)?;
+    Ok(((x, y), rect))
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn eof(&self) -> bool {
+        self.pos >= self.chars.len()
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_ws(&mut self) {
+        while self.peek().map(char::is_whitespace).unwrap_or(false) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(anyhow!("expected `{}` at {}", c, self.pos))
+        }
+    }
+
+    fn ident(&mut self) -> String {
+        let start = self.pos;
+        while self
+            .peek()
+            .map(|c| c.is_alphanumeric() || c == '_' || c == '-')
+            .unwrap_or(false)
+        {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn step(&mut self) -> Result<Step> {
+        let start = self.pos;
+        let mut step = Step::default();
+        if self.peek() == Some('*') {
+            self.pos += 1;
+        } else {
+            let role = self.ident();
+            if !role.is_empty() {
+                step.role = Some(role);
+            }
+        }
+        loop {
+            match self.peek() {
+                Some('[') => {
+                    self.pos += 1;
+                    step.attrs.push(self.attr()?);
+                }
+                Some(':') => {
+                    self.pos += 1;
+                    step.nth_child = Some(self.pseudo()?);
+                }
+                _ => break,
+            }
+        }
+        if self.pos == start {
+            bail!("expected element at {}", self.pos);
+        }
+        Ok(step)
+    }
+
+    fn attr(&mut self) -> Result<(Attr, Pattern)> {
+        self.skip_ws();
+        let name = self.ident();
+        let attr = match name.as_str() {
+            "role" => Attr::Role,
+            "label" | "title" => Attr::Label,
+            "value" => Attr::Value,
+            other => bail!("unknown attribute `{}`", other),
+        };
+        self.skip_ws();
+        let is_regex = self.peek() == Some('~');
+        if is_regex {
+            self.pos += 1;
+        }
+        self.expect('=')?;
+        self.skip_ws();
+        let raw = self.value()?;
+        self.skip_ws();
+        self.expect(']')?;
+        let pat = if is_regex {
+            Pattern::Regex(Regex::new(&raw)?)
+        } else {
+            Pattern::Glob(Glob::new(&raw)?.compile_matcher())
+        };
+        Ok((attr, pat))
+    }
+
+    fn value(&mut self) -> Result<String> {
+        let mut out = String::new();
+        match self.peek() {
+            Some(q @ ('"' | '\'')) => {
+                self.pos += 1;
+                loop {
+                    match self.peek() {
+                        None => bail!("unterminated string"),
+                        Some('\\') => {
+                            self.pos += 1;
+                            let c = self.peek().ok_or_else(|| anyhow!("unterminated string"))?;
+                            // keep regex escapes such as `\d` intact
+                            if c != q && c != '\\' {
+                                out.push('\\');
+                            }
+                            out.push(c);
+                        }
+                        Some(c) if c == q => {
+                            self.pos += 1;
+                            break;
+                        }
+                        Some(c) => out.push(c),
+                    }
+                    self.pos += 1;
+                }
+            }
+            _ => {
+                while let Some(c) = self.peek() {
+                    if c == ']' || c.is_whitespace() {
+                        break;
+                    }
+                    out.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    fn pseudo(&mut self) -> Result<usize> {
+        let name = self.ident();
+        if name != "nth-child" {
+            bail!("unknown pseudo-class `:{}`", name);
+        }
+        self.expect('(')?;
+        self.skip_ws();
+        let digits = self.ident();
+        let n: usize = digits
+            .parse()
+            .map_err(|_| anyhow!("invalid nth-child index `{}`", digits))?;
+        if n == 0 {
+            bail!("nth-child index is 1-based");
+        }
+        self.skip_ws();
+        self.expect(')')?;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::node;
+
+    fn sample() -> UiNode {
+        node(
+            "AXWindow",
+            "Login",
+            None,
+            vec![
+                node(
+                    "AXGroup",
+                    "",
+                    None,
+                    vec![
+                        node("AXTextField", "Email", Some("a"), vec![]),
+                        node("AXTextField", "Order", Some("A-1234"), vec![]),
+                    ],
+                ),
+                node("AXButton", "Save", None, vec![]),
+                node("AXButton", "Save As…", None, vec![]),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_role_and_label_glob() {
+        let tree = sample();
+        let sel = Selector::parse(r#"AXButton[label="Save"]"#).unwrap();
+        assert_eq!(sel.find_one(&tree).unwrap().label, "Save");
+
+        let sel = Selector::parse(r#"AXButton[title="Save*"]"#).unwrap();
+        assert_eq!(sel.find_all(&tree).0.len(), 2);
+    }
+
+    #[test]
+    fn test_value_regex_and_nth_child() {
+        let tree = sample();
+        let sel = Selector::parse(r#"[value~="^A-\d+$"]"#).unwrap();
+        assert_eq!(sel.find_one(&tree).unwrap().label, "Order");
+
+        let sel = Selector::parse("AXGroup > AXTextField:nth-child(1)").unwrap();
+        assert_eq!(sel.find_one(&tree).unwrap().label, "Email");
+    }
+
+    #[test]
+    fn test_path_to_match() {
+        let tree = sample();
+        let sel = Selector::parse(r#"[label="Order"]"#).unwrap();
+        let node = sel.find_one(&tree).unwrap();
+        assert_eq!(tree.path_to(node), Some(vec![0, 1]));
+        assert_eq!(tree.path_to(&sample()), None);
+    }
+
+    #[test]
+    fn test_ancestor_path() {
+        let tree = sample();
+        let sel = Selector::parse("AXWindow AXTextField").unwrap();
+        assert_eq!(sel.find_all(&tree).0.len(), 2);
+
+        let sel = Selector::parse("AXWindow > AXTextField").unwrap();
+        assert_eq!(sel.find_all(&tree).0.len(), 0);
+    }
+
+    #[test]
+    fn test_match_count_error() {
+        let tree = sample();
+        let err = Selector::parse("AXCheckBox")
+            .unwrap()
+            .find_one(&tree)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "selector `AXCheckBox` matched 0/6 elements"
+        );
+
+        let err = Selector::parse("AXButton")
+            .unwrap()
+            .find_one(&tree)
+            .unwrap_err();
+        assert!(err.to_string().contains("matched 2/6"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Selector::parse("").is_err());
+        assert!(Selector::parse("> AXButton").is_err());
+        assert!(Selector::parse("AXButton >").is_err());
+        assert!(Selector::parse("AXButton[foo=bar]").is_err());
+        assert!(Selector::parse("AXButton:nth-child(0)").is_err());
+        assert!(Selector::parse(r#"AXButton[label="x"#).is_err());
+    }
+}
@@ -0,0 +1,367 @@
+// src/store.rs
+
+use crate::job::{Artifact, JobResult, JobStatus, StepRecord};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const JOBS_DIR: &str = ".thin-sag/jobs";
+const JOBS_FILE: &str = "jobs.jsonl";
+
+/// Where `JobManager` keeps jobs between restarts.
+pub trait JobStore: Send + Sync + 'static {
+    /// Latest saved state of every job, oldest first.
+    fn load(&self) -> Result<Vec<(String, JobResult)>>;
+    /// Record the current state of a job.
+    fn save(&self, id: &str, job: &JobResult) -> Result<()>;
+    /// Record a finished step of a running job, without rewriting the rest.
+    fn save_step(&self, id: &str, step: &StepRecord) -> Result<()>;
+    /// Record artifact `n` of a running job as soon as it is captured.
+    fn save_artifact(&self, id: &str, n: usize, artifact: &Artifact) -> Result<()>;
+    /// Forget jobs evicted by the retention policy.
+    fn remove(&self, ids: &[String]) -> Result<()>;
+}
+
+/// Keeps nothing: jobs live only in the `JobManager` map.
+pub struct MemoryStore;
+
+impl JobStore for MemoryStore {
+    fn load(&self) -> Result<Vec<(String, JobResult)>> {
+        Ok(vec![])
+    }
+
+    fn save(&self, _: &str, _: &JobResult) -> Result<()> {
+        Ok(())
+    }
+
+    fn save_step(&self, _: &str, _: &StepRecord) -> Result<()> {
+        Ok(())
+    }
+
+    fn save_artifact(&self, _: &str, _: usize, _: &Artifact) -> Result<()> {
+        Ok(())
+    }
+
+    fn remove(&self, _: &[String]) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Append-only store under `~/.thin-sag/jobs`: `jobs.jsonl` gets one line per
+/// saved state (the last line of an id wins), plus one line per finished step
+/// or captured artifact that is folded into the state before it, and artifact
+/// data is written once to `<id>/<n>.<ext>`. `load` compacts the file to one line per job.
+pub struct JsonlStore {
+    dir: PathBuf,
+    /// serializes appends and compaction
+    lock: Mutex<()>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    id: String,
+    job: JobResult,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StepEntry {
+    id: String,
+    step: StepRecord,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ArtifactEntry {
+    id: String,
+    n: usize,
+    artifact: Artifact,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Line {
+    Job(Box<Entry>),
+    Step(StepEntry),
+    Artifact(ArtifactEntry),
+}
+
+impl JsonlStore {
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+        Ok(Self {
+            dir,
+            lock: Mutex::new(()),
+        })
+    }
+
+    pub fn open_default() -> Result<Self> {
+        Self::open(
+            dirs::home_dir()
+                .unwrap_or(PathBuf::from("/"))
+                .join(JOBS_DIR),
+        )
+    }
+
+    fn file(&self) -> PathBuf {
+        self.dir.join(JOBS_FILE)
+    }
+
+    fn artifact_path(&self, id: &str, n: usize, ext: &str) -> PathBuf {
+        self.dir.join(id).join(format!("{}.{}", n, ext))
+    }
+
+    /// Write the data of artifact `n` unless it is already there; the caller
+    /// holds `lock`.
+    fn write_artifact(&self, id: &str, n: usize, art: &Artifact) -> Result<()> {
+        let path = self.artifact_path(id, n, art.extension());
+        if !path.exists() {
+            fs::create_dir_all(self.dir.join(id))?;
+            fs::write(&path, art.data.as_slice())?;
+        }
+        Ok(())
+    }
+
+    /// Add one line to `jobs.jsonl`; the caller holds `lock`.
+    fn append(&self, line: &str) -> Result<()> {
+        let mut f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.file())?;
+        writeln!(f, "{}", line)?;
+        Ok(())
+    }
+}
+
+impl JobStore for JsonlStore {
+    /// Jobs that were `Pending` or `Running` when the server stopped will
+    /// never finish; they come back as `Interrupted`.
+    fn load(&self) -> Result<Vec<(String, JobResult)>> {
+        let _guard = self.lock.lock().unwrap();
+        let path = self.file();
+        let mut out = read_latest(&path)?;
+        for (id, job) in &mut out {
+            if matches!(job.status, JobStatus::Pending | JobStatus::Running) {
+                job.status = JobStatus::Interrupted;
+                job.output = Some("interrupted by a server restart".into());
+            }
+            for (n, art) in job.artifacts.iter_mut().enumerate() {
+                let data = fs::read(self.artifact_path(id, n, art.extension())).unwrap_or_default();
+                art.data = Arc::new(data);
+            }
+        }
+        compact(&path, &out)?;
+        Ok(out)
+    }
+
+    fn save(&self, id: &str, job: &JobResult) -> Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        for (n, art) in job.artifacts.iter().enumerate() {
+            self.write_artifact(id, n, art)?;
+        }
+        let line = serde_json::to_string(&Entry {
+            id: id.to_string(),
+            job: job.clone(),
+        })?;
+        self.append(&line)
+    }
+
+    fn save_step(&self, id: &str, step: &StepRecord) -> Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let line = serde_json::to_string(&StepEntry {
+            id: id.to_string(),
+            step: step.clone(),
+        })?;
+        self.append(&line)
+    }
+
+    fn save_artifact(&self, id: &str, n: usize, artifact: &Artifact) -> Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        self.write_artifact(id, n, artifact)?;
+        let line = serde_json::to_string(&ArtifactEntry {
+            id: id.to_string(),
+            n,
+            artifact: artifact.clone(),
+        })?;
+        self.append(&line)
+    }
+
+    fn remove(&self, ids: &[String]) -> Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let path = self.file();
+        let mut jobs = read_latest(&path)?;
+        jobs.retain(|(id, _)| !ids.contains(id));
+        compact(&path, &jobs)?;
+        for id in ids {
+            let dir = self.dir.join(id);
+            if dir.exists() {
+                fs::remove_dir_all(&dir)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The last saved state of every job in `path`, with the steps saved after
+/// it folded in, in first-seen order.
+fn read_latest(path: &Path) -> Result<Vec<(String, JobResult)>> {
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let mut order = Vec::new();
+    let mut jobs = HashMap::new();
+    for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        // a crash can leave a torn last line
+        match serde_json::from_str::<Line>(&line) {
+            Ok(Line::Job(entry)) => {
+                let Entry { id, job } = *entry;
+                if jobs.insert(id.clone(), job).is_none() {
+                    order.push(id);
+                }
+            }
+            Ok(Line::Step(StepEntry { id, step })) => {
+                // one line per executed step: repeat iterations share an id
+                if let Some(job) = jobs.get_mut(&id) {
+                    job.steps.push(step);
+                }
+            }
+            Ok(Line::Artifact(ArtifactEntry { id, n, artifact })) => match jobs.get_mut(&id) {
+                Some(job) if job.artifacts.len() == n => job.artifacts.push(artifact),
+                _ => {}
+            },
+            Err(_) => {
+                eprintln!("[store] skipping bad line {} of {}", i + 1, path.display());
+            }
+        }
+    }
+    Ok(order
+        .into_iter()
+        .map(|id| {
+            let job = jobs.remove(&id).unwrap();
+            (id, job)
+        })
+        .collect())
+}
+
+/// Rewrite `path` with one line per job, via a temp file and rename.
+fn compact(path: &Path, jobs: &[(String, JobResult)]) -> Result<()> {
+    let tmp = path.with_extension("jsonl.tmp");
+    let mut f = File::create(&tmp)?;
+    for (id, job) in jobs {
+        let entry = Entry {
+            id: id.clone(),
+            job: job.clone(),
+        };
+        writeln!(f, "{}", serde_json::to_string(&entry)?)?;
+    }
+    f.sync_all()?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_jsonl_store_reload_marks_running_interrupted() {
+        let tmp = TempDir::new().unwrap();
+        let store = JsonlStore::open(tmp.path()).unwrap();
+
+        let mut done = JobResult::pending();
+        store.save("a", &done).unwrap();
+        done.status = JobStatus::Success;
+        done.artifacts.push(Artifact {
+            step: "0".into(),
+            name: Some("shot".into()),
+            content_type: "image/png".into(),
+            data: Arc::new(b"\x89PNG".to_vec()),
+        });
+        store.save("a", &done).unwrap();
+
+        let mut running = JobResult::pending();
+        running.status = JobStatus::Running;
+        store.save("b", &running).unwrap();
+
+        let fresh = JsonlStore::open(tmp.path()).unwrap();
+        let jobs = fresh.load().unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].0, "a");
+        assert_eq!(jobs[0].1.status, JobStatus::Success);
+        assert_eq!(jobs[0].1.artifacts[0].data.as_slice(), b"\x89PNG");
+        assert_eq!(jobs[1].1.status, JobStatus::Interrupted);
+
+        let lines = fs::read_to_string(tmp.path().join(JOBS_FILE)).unwrap();
+        assert_eq!(lines.lines().count(), 2);
+
+        fresh.remove(&["a".to_string()]).unwrap();
+        assert!(!tmp.path().join("a").exists());
+        let jobs = fresh.load().unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].0, "b");
+    }
+
+    #[test]
+    fn test_jsonl_store_steps_grow_the_file_linearly() {
+        let tmp = TempDir::new().unwrap();
+        let store = JsonlStore::open(tmp.path()).unwrap();
+        let size = || fs::metadata(tmp.path().join(JOBS_FILE)).unwrap().len();
+
+        let mut job = JobResult::pending();
+        job.status = JobStatus::Running;
+        store.save("a", &job).unwrap();
+        let start = size();
+        let step = |n: usize| StepRecord {
+            step: format!("{:03}", n),
+            act: "wait".into(),
+            meta: Default::default(),
+            status: JobStatus::Success,
+            started_at: job.created_at,
+            duration_ms: 5,
+            attempts: 1,
+            error: None,
+        };
+        for n in 0..50 {
+            store.save_step("a", &step(n)).unwrap();
+        }
+        let first = size() - start;
+        for n in 50..100 {
+            store.save_step("a", &step(n)).unwrap();
+        }
+        assert_eq!(size() - start, 2 * first);
+
+        let jobs = JsonlStore::open(tmp.path()).unwrap().load().unwrap();
+        assert_eq!(jobs[0].1.steps.len(), 100);
+        assert_eq!(jobs[0].1.steps[99].step, "099");
+    }
+
+    #[test]
+    fn test_jsonl_store_keeps_artifacts_of_interrupted_jobs() {
+        let tmp = TempDir::new().unwrap();
+        let store = JsonlStore::open(tmp.path()).unwrap();
+        let mut job = JobResult::pending();
+        job.status = JobStatus::Running;
+        store.save("a", &job).unwrap();
+        let shot = Artifact {
+            step: "2".into(),
+            name: Some("shot".into()),
+            content_type: "image/png".into(),
+            data: Arc::new(b"\x89PNG".to_vec()),
+        };
+        store.save_artifact("a", 0, &shot).unwrap();
+
+        let jobs = JsonlStore::open(tmp.path()).unwrap().load().unwrap();
+        assert_eq!(jobs[0].1.status, JobStatus::Interrupted);
+        assert_eq!(jobs[0].1.artifacts.len(), 1);
+        assert_eq!(jobs[0].1.artifacts[0].step, "2");
+        assert_eq!(jobs[0].1.artifacts[0].data.as_slice(), b"\x89PNG");
+    }
+}
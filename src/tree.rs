@@ -7,7 +7,7 @@ use core_graphics::display::CGRect;
 use globset::Glob;
 use objc::runtime::{Class, Object};
 use objc::{msg_send, sel, sel_impl};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::mem;
 use std::ptr;
 
@@ -32,28 +32,100 @@ extern "C" {
     fn AXValueGetValue(value: CFTypeRef, valueType: u32, ptr: *mut std::ffi::c_void) -> bool;
     fn CFGetTypeID(cf: CFTypeRef) -> usize;
     fn CFStringGetTypeID() -> usize;
+    fn CFBooleanGetTypeID() -> usize;
+    fn CFBooleanGetValue(boolean: CFTypeRef) -> bool;
 }
 
 const kAXValueCGPointType: u32 = 2;
 const kAXValueCGSizeType: u32 = 4;
 const kAXValueCGRectType: u32 = 3;
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Rect {
     pub x: f64,
     pub y: f64,
     pub width: f64,
     pub height: f64,
 }
-#[derive(Serialize)]
+
+impl Rect {
+    pub fn center(&self) -> (f64, f64) {
+        (self.x + self.width / 2.0, self.y + self.height / 2.0)
+    }
+}
+
+/// One element of a snapshot. Deserializable so that callers can supply a
+/// snapshot for dry runs.
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct UiNode {
     pub role: String,
+    #[serde(default)]
     pub label: String,
     pub value: Option<String>,
+    pub enabled: Option<bool>,
     pub rect: Option<Rect>,
+    #[serde(default)]
     pub children: Vec<UiNode>,
 }
 
+impl UiNode {
+    /// Copy of this node keeping at most `depth` levels of children.
+    pub fn trimmed(&self, depth: usize) -> UiNode {
+        UiNode {
+            role: self.role.clone(),
+            label: self.label.clone(),
+            value: self.value.clone(),
+            enabled: self.enabled,
+            rect: self.rect.clone(),
+            children: if depth == 0 {
+                vec![]
+            } else {
+                self.children.iter().map(|c| c.trimmed(depth - 1)).collect()
+            },
+        }
+    }
+
+    /// Copy of this node with every label and value passed through
+    /// `mask_text`, for trees that leave the process. Snapshots mask values
+    /// only, since selectors match on labels.
+    pub fn masked(&self) -> UiNode {
+        UiNode {
+            role: self.role.clone(),
+            label: crate::mask::mask_text(&self.label),
+            value: self.value.as_deref().map(crate::mask::mask_text),
+            enabled: self.enabled,
+            rect: self.rect.clone(),
+            children: self.children.iter().map(UiNode::masked).collect(),
+        }
+    }
+
+    /// Child indexes leading from this node to `target` (compared by address).
+    pub fn path_to(&self, target: &UiNode) -> Option<Vec<usize>> {
+        if std::ptr::eq(self, target) {
+            return Some(vec![]);
+        }
+        self.children.iter().enumerate().find_map(|(i, c)| {
+            c.path_to(target).map(|mut path| {
+                path.insert(0, i);
+                path
+            })
+        })
+    }
+}
+
+/// An enabled node without a rect, for building snapshots in tests.
+#[cfg(test)]
+pub(crate) fn node(role: &str, label: &str, value: Option<&str>, children: Vec<UiNode>) -> UiNode {
+    UiNode {
+        role: role.into(),
+        label: label.into(),
+        value: value.map(Into::into),
+        enabled: Some(true),
+        rect: None,
+        children,
+    }
+}
+
 unsafe fn cf_to_string(cf: CFTypeRef) -> Option<String> {
     if cf.is_null() {
         return None;
@@ -70,7 +142,20 @@ unsafe fn cf_to_string(cf: CFTypeRef) -> Option<String> {
     Some(r)
 }
 
-fn get_attr(element: *mut Object, name: &str) -> Option<CFTypeRef> {
+unsafe fn cf_to_bool(cf: CFTypeRef) -> Option<bool> {
+    if cf.is_null() {
+        return None;
+    }
+    let r = if CFGetTypeID(cf) == CFBooleanGetTypeID() {
+        Some(CFBooleanGetValue(cf))
+    } else {
+        None
+    };
+    CFRelease(cf);
+    r
+}
+
+pub(crate) fn get_attr(element: *mut Object, name: &str) -> Option<CFTypeRef> {
     unsafe {
         let cf_name = CFString::new(name).as_CFTypeRef();
         let mut out: CFTypeRef = ptr::null_mut();
@@ -98,6 +183,27 @@ unsafe fn list_windows(app_ax: *mut Object) -> Vec<*mut Object> {
     }
 }
 
+/// AXChildren of `element`, in the order `build` visits them.
+pub(crate) fn children(element: *mut Object) -> Vec<*mut Object> {
+    match get_attr(element, "AXChildren") {
+        Some(cf_arr) => unsafe {
+            let arr: *mut Object = mem::transmute(cf_arr);
+            let count: usize = msg_send![arr, count];
+            (0..count)
+                .map(|i| {
+                    let c: *mut Object = msg_send![arr, objectAtIndex: i];
+                    c
+                })
+                .collect()
+        },
+        None => vec![],
+    }
+}
+
+pub(crate) fn string_attr(element: *mut Object, name: &str) -> Option<String> {
+    get_attr(element, name).and_then(|cf| unsafe { cf_to_string(cf) })
+}
+
 unsafe fn select_window(wins: &[*mut Object], sel: &WindowSelector) -> Option<*mut Object> {
     match sel {
         WindowSelector::Front => wins.first().copied(),
@@ -125,7 +231,8 @@ unsafe fn select_window(wins: &[*mut Object], sel: &WindowSelector) -> Option<*m
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "lowercase")]
 pub enum WindowSelector {
     Front,
     Index(usize),
@@ -175,6 +282,9 @@ unsafe fn build(node: *mut Object, depth: usize) -> UiNode {
         }
     }
 
+    // AXEnabled
+    let enabled = get_attr(node, "AXEnabled").and_then(|cf| unsafe { cf_to_bool(cf) });
+
     // マスク処理
     let masked_value = value.as_deref().map(crate::mask::mask_text);
 
@@ -250,6 +360,7 @@ unsafe fn build(node: *mut Object, depth: usize) -> UiNode {
         role,
         label,
         value: masked_value,
+        enabled,
         rect,
         children,
     }
@@ -303,22 +414,40 @@ pub fn list_windows_info() -> Vec<WindowInfo> {
     }
 }
 
+/// Every AX window of every running app, with the owning app's pid.
+unsafe fn all_windows() -> Result<Vec<(i32, *mut Object)>> {
+    let ws_cls = Class::get("NSWorkspace").ok_or_else(|| anyhow!("NSWorkspace not found"))?;
+    let ws: *mut Object = msg_send![ws_cls, sharedWorkspace];
+    let running_apps: *mut Object = msg_send![ws, runningApplications];
+    let app_count: usize = msg_send![running_apps, count];
+    let mut wins = Vec::new();
+    for i in 0..app_count {
+        let app: *mut Object = msg_send![running_apps, objectAtIndex: i];
+        let pid: i32 = msg_send![app, processIdentifier];
+        let ax_app = AXUIElementCreateApplication(pid);
+        if !ax_app.is_null() {
+            wins.extend(list_windows(ax_app).into_iter().map(|w| (pid, w)));
+        }
+    }
+    Ok(wins)
+}
+
+/// The AX window element matching `sel` and the pid of the app owning it.
+pub(crate) fn find_window(sel: &WindowSelector) -> Result<(i32, *mut Object)> {
+    unsafe {
+        let wins = all_windows()?;
+        let elems: Vec<*mut Object> = wins.iter().map(|(_, w)| *w).collect();
+        let target =
+            select_window(&elems, sel).ok_or_else(|| anyhow!("window {:?} not found", sel))?;
+        wins.into_iter()
+            .find(|(_, w)| *w == target)
+            .ok_or_else(|| anyhow!("window {:?} not found", sel))
+    }
+}
+
 pub fn snapshot_tree(sel: WindowSelector) -> Result<UiNode> {
     unsafe {
-        let ws_cls = Class::get("NSWorkspace").ok_or_else(|| anyhow!("NSWorkspace not found"))?;
-        let ws: *mut Object = msg_send![ws_cls, sharedWorkspace];
-        let running_apps: *mut Object = msg_send![ws, runningApplications];
-        let app_count: usize = msg_send![running_apps, count];
-        let mut wins = Vec::new();
-        for i in 0..app_count {
-            let app: *mut Object = msg_send![running_apps, objectAtIndex: i];
-            let pid: i32 = msg_send![app, processIdentifier];
-            let ax_app = AXUIElementCreateApplication(pid);
-            if !ax_app.is_null() {
-                let mut app_wins = list_windows(ax_app);
-                wins.append(&mut app_wins);
-            }
-        }
+        let wins: Vec<*mut Object> = all_windows()?.into_iter().map(|(_, w)| w).collect();
 
         eprintln!("DEBUG: system-wide windows count: {}", wins.len());
         for (i, w) in wins.iter().enumerate() {
@@ -335,3 +464,33 @@ pub fn snapshot_tree(sel: WindowSelector) -> Result<UiNode> {
         Ok(build(target, 0))
     }
 }
+
+/// Live AX element at `path` (child indexes, see `UiNode::path_to`) under
+/// the window matching `sel`.
+pub(crate) fn element_at(sel: &WindowSelector, path: &[usize]) -> Result<*mut Object> {
+    let (_, mut element) = find_window(sel)?;
+    for &i in path {
+        element = *children(element)
+            .get(i)
+            .ok_or_else(|| anyhow!("element is gone (the window changed)"))?;
+    }
+    Ok(element)
+}
+
+/// AX element of the frontmost application.
+pub(crate) fn front_app() -> Result<*mut Object> {
+    unsafe {
+        let ws_cls = Class::get("NSWorkspace").ok_or_else(|| anyhow!("NSWorkspace not found"))?;
+        let ws: *mut Object = msg_send![ws_cls, sharedWorkspace];
+        let app: *mut Object = msg_send![ws, frontmostApplication];
+        if app.is_null() {
+            return Err(anyhow!("no frontmost application"));
+        }
+        let pid: i32 = msg_send![app, processIdentifier];
+        let ax_app = AXUIElementCreateApplication(pid);
+        if ax_app.is_null() {
+            return Err(anyhow!("no AX element for pid {}", pid));
+        }
+        Ok(ax_app)
+    }
+}
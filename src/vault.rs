@@ -1,3 +1,4 @@
+use security_framework::item::{ItemClass, ItemSearchOptions};
 use security_framework::os::macos::passwords::find_generic_password;
 
 pub fn get_secret(label: &str) -> Result<String, anyhow::Error> {
@@ -8,3 +9,14 @@ pub fn get_secret(label: &str) -> Result<String, anyhow::Error> {
 
     Ok(s)
 }
+
+/// Whether a secret with this label exists, without reading its value.
+pub fn has_secret(label: &str) -> bool {
+    ItemSearchOptions::new()
+        .class(ItemClass::generic_password())
+        .service(label)
+        .load_attributes(true)
+        .limit(1)
+        .search()
+        .is_ok_and(|found| !found.is_empty())
+}
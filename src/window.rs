@@ -0,0 +1,140 @@
+// src/window.rs
+
+use crate::tree::{find_window, get_attr, WindowSelector};
+use anyhow::{anyhow, bail, Result};
+use core_foundation::base::{CFTypeRef, TCFType};
+use core_foundation::boolean::CFBoolean;
+use core_foundation::string::CFString;
+use core_graphics::geometry::{CGPoint, CGSize};
+use objc::runtime::{Class, Object, BOOL, NO};
+use objc::{msg_send, sel, sel_impl};
+use std::ffi::c_void;
+
+#[link(name = "ApplicationServices", kind = "framework")]
+extern "C" {
+    fn AXUIElementSetAttributeValue(
+        element: *mut Object,
+        attribute: CFTypeRef,
+        value: CFTypeRef,
+    ) -> i32;
+    fn AXUIElementPerformAction(element: *mut Object, action: CFTypeRef) -> i32;
+    fn AXValueCreate(value_type: u32, value: *const c_void) -> CFTypeRef;
+    fn CFRelease(cf: CFTypeRef);
+}
+
+const AX_VALUE_CG_POINT: u32 = 2;
+const AX_VALUE_CG_SIZE: u32 = 4;
+/// NSApplicationActivateIgnoringOtherApps
+const ACTIVATE_IGNORING_OTHER_APPS: usize = 1 << 1;
+/// time for the window server to hand keyboard focus over
+const FOCUS_SETTLE_MS: u64 = 100;
+
+pub(crate) fn set_attr(element: *mut Object, name: &str, value: CFTypeRef) -> Result<()> {
+    let err =
+        unsafe { AXUIElementSetAttributeValue(element, CFString::new(name).as_CFTypeRef(), value) };
+    if err != 0 {
+        bail!("setting {} failed (AXError {})", name, err);
+    }
+    Ok(())
+}
+
+pub(crate) fn perform(element: *mut Object, action: &str) -> Result<()> {
+    let err = unsafe { AXUIElementPerformAction(element, CFString::new(action).as_CFTypeRef()) };
+    if err != 0 {
+        bail!("{} failed (AXError {})", action, err);
+    }
+    Ok(())
+}
+
+/// Brings the owning app to the front and makes `sel` its main window, so
+/// that keyboard input lands there.
+pub fn focus(sel: &WindowSelector) -> Result<()> {
+    let (pid, win) = find_window(sel)?;
+    unsafe {
+        let cls = Class::get("NSRunningApplication")
+            .ok_or_else(|| anyhow!("NSRunningApplication not found"))?;
+        let app: *mut Object = msg_send![cls, runningApplicationWithProcessIdentifier: pid];
+        if app.is_null() {
+            bail!("app of window {:?} is gone", sel);
+        }
+        let _: BOOL = msg_send![app, activateWithOptions: ACTIVATE_IGNORING_OTHER_APPS];
+    }
+    perform(win, "AXRaise")?;
+    set_attr(win, "AXMain", CFBoolean::true_value().as_CFTypeRef())?;
+    std::thread::sleep(std::time::Duration::from_millis(FOCUS_SETTLE_MS));
+    Ok(())
+}
+
+/// Moves the window's top-left corner to `x`/`y` (global display points).
+pub fn move_to(sel: &WindowSelector, x: i32, y: i32) -> Result<()> {
+    let (_, win) = find_window(sel)?;
+    let pt = CGPoint::new(x as f64, y as f64);
+    set_value(
+        win,
+        "AXPosition",
+        AX_VALUE_CG_POINT,
+        &pt as *const _ as *const c_void,
+    )
+}
+
+pub fn resize(sel: &WindowSelector, width: u32, height: u32) -> Result<()> {
+    let (_, win) = find_window(sel)?;
+    let size = CGSize::new(width as f64, height as f64);
+    set_value(
+        win,
+        "AXSize",
+        AX_VALUE_CG_SIZE,
+        &size as *const _ as *const c_void,
+    )
+}
+
+pub fn minimize(sel: &WindowSelector) -> Result<()> {
+    let (_, win) = find_window(sel)?;
+    set_attr(win, "AXMinimized", CFBoolean::true_value().as_CFTypeRef())
+}
+
+/// Presses the window's close button, as a user would; the app may still
+/// ask to save first.
+pub fn close(sel: &WindowSelector) -> Result<()> {
+    let (_, win) = find_window(sel)?;
+    let button = get_attr(win, "AXCloseButton")
+        .ok_or_else(|| anyhow!("window {:?} has no close button", sel))?;
+    let res = perform(button as *mut Object, "AXPress");
+    unsafe { CFRelease(button) };
+    res
+}
+
+/// Asks every running instance of bundle id `target` to quit.
+pub fn quit_app(target: &str) -> Result<()> {
+    unsafe {
+        let cls = Class::get("NSRunningApplication")
+            .ok_or_else(|| anyhow!("NSRunningApplication not found"))?;
+        let id = CFString::new(target);
+        let apps: *mut Object = msg_send![
+            cls,
+            runningApplicationsWithBundleIdentifier: id.as_concrete_TypeRef() as *mut Object
+        ];
+        let count: usize = msg_send![apps, count];
+        if count == 0 {
+            bail!("`{}` is not running", target);
+        }
+        for i in 0..count {
+            let app: *mut Object = msg_send![apps, objectAtIndex: i];
+            let ok: BOOL = msg_send![app, terminate];
+            if ok == NO {
+                bail!("`{}` refused to quit", target);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn set_value(win: *mut Object, name: &str, value_type: u32, value: *const c_void) -> Result<()> {
+    let ax_value = unsafe { AXValueCreate(value_type, value) };
+    if ax_value.is_null() {
+        bail!("could not encode {}", name);
+    }
+    let res = set_attr(win, name, ax_value);
+    unsafe { CFRelease(ax_value) };
+    res
+}